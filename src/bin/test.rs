@@ -1,17 +1,29 @@
 use controller_emulator::controller::ns_procon;
 use controller_emulator::controller::Controller;
-use controller_emulator::usb_gadget;
+use controller_emulator::usb_gadget::ns_procon::ns_procons;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::sleep;
 use std::time::Duration;
 
 fn main() {
-    let _ = usb_gadget::reset("procons");
-    let mut procon_1 = ns_procon::NsProcon::create("/dev/hidg0", [255, 0, 0]);
+    let gadget = ns_procons()
+        .instantiate("procons")
+        .expect("Couldn't set up the gadget");
+    let hidg_1 = gadget
+        .wait_for_hid_device(0, Duration::from_secs(5))
+        .expect("Couldn't find the HID device");
+    let mut procon_1 = ns_procon::NsProcon::create(&hidg_1, [255, 0, 0]);
     // let mut procon_1 = ns_procon::NsProcon::create_separate("test.out", "test.in", [255, 0, 0]);
     // let mut procon_2 = ns_procon::NsProcon::create("/dev/hidg1", [0, 150, 0]);
     // let mut procon_3 = ns_procon::NsProcon::create("/dev/hidg2", [255, 255, 0]);
     // let mut procon_4 = ns_procon::NsProcon::create("/dev/hidg3", [40, 40, 255]);
 
+    // Stop on Ctrl-C so the gadget gets torn down
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || r.store(false, Ordering::SeqCst)).expect("Couldn't set handler");
+
     println!("Starting procon 1");
     procon_1
         .start_comms()
@@ -30,6 +42,9 @@ fn main() {
     //     .expect("Couldn't start communicating");
 
     for _i in 0..100 {
+        if !running.load(Ordering::SeqCst) {
+            break;
+        }
         let _ = procon_1.press(ns_procon::inputs::BUTTON_A, true);
         // procon_2.press(ns_procon::inputs::BUTTON_A, true);
         // procon_3.press(ns_procon::inputs::BUTTON_A, true);
@@ -46,4 +61,6 @@ fn main() {
     // procon_2.stop();
     // procon_3.stop();
     // procon_4.stop();
+
+    gadget.close().expect("Couldn't remove the gadget");
 }
@@ -1,10 +1,100 @@
 use anyhow::Result;
 use std::sync::mpsc::Receiver;
+use std::time::Duration;
+pub mod gc_adapter;
+pub mod hid_gamepad;
+pub mod hori_pad;
+pub mod keyboard;
 pub mod ns_procon;
+mod outbox;
+
+/// One frequency band of an HD rumble motor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RumbleBand {
+    /// Frequency in Hz
+    pub frequency: f32,
+    /// Amplitude from 0.0 (off) to 1.0
+    pub amplitude: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rumble {
+    pub high: RumbleBand,
+    pub low: RumbleBand,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LedState {
+    Off,
+    On,
+    Flashing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerLights {
+    pub leds: [LedState; 4],
+    /// The player number (1-8) the pattern stands for, if it is one of the
+    /// patterns the Switch assigns
+    pub player: Option<u8>,
+}
+
+/// One step of a home button LED pattern. Fade and duration are multiples
+/// of the pattern's base duration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HomeLightCycle {
+    pub intensity: u8,
+    pub fade: u8,
+    pub duration: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HomeLight {
+    /// Zero when the LED is off
+    pub base_duration: Duration,
+    pub start_intensity: u8,
+    /// Number of times the cycles are played, 0 repeats forever
+    pub repeat: u8,
+    pub cycles: Vec<HomeLightCycle>,
+}
+
+/// Steps of the Switch controller handshake, roughly in the order the
+/// Switch performs them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HandshakeStage {
+    /// 0x80 0x01: the Switch asked for the controller type and MAC address
+    Status,
+    /// 0x80 0x02
+    Handshake,
+    /// 0x80 0x03: switched to the faster baud rate
+    BaudRate,
+    /// 0x80 0x04: the Switch stopped timing out and talks HID only, so
+    /// input reports start flowing
+    HidOnly,
+    /// Subcommand 0x02: the Switch read the device info
+    DeviceInfo,
+    /// Subcommand 0x03: the Switch chose an input report mode
+    InputMode,
+    /// Subcommand 0x30: a player number was assigned and the Switch is
+    /// accepting input
+    Active,
+    /// 0x80 0x05: the Switch re-enabled the timeout and paused input
+    Suspended,
+}
 
 pub enum ControllerEvent {
+    /// Sent when the handshake reaches `HandshakeStage::Active`
     InputActive,
-    PlayerLights(u8),
+    Handshake(HandshakeStage),
+    /// An output report the emulator didn't recognise, with its raw bytes
+    UnknownReport(Vec<u8>),
+    PlayerLights(PlayerLights),
+    HomeLight(HomeLight),
+    Rumble {
+        left: Rumble,
+        right: Rumble,
+    },
+    /// GameCube adapter rumble motors, one per port
+    GcRumble([bool; 4]),
 }
 
 pub trait Controller {
@@ -23,3 +113,18 @@ pub trait Controller {
 
     fn log_state(&self);
 }
+
+/// Encodes a d-pad as a HID hat switch value (0 = up, clockwise, 8 = neutral)
+pub(crate) fn hat_switch(up: bool, right: bool, down: bool, left: bool) -> u8 {
+    match (right as i8 - left as i8, up as i8 - down as i8) {
+        (0, 1) => 0,
+        (1, 1) => 1,
+        (1, 0) => 2,
+        (1, -1) => 3,
+        (0, -1) => 4,
+        (-1, -1) => 5,
+        (-1, 0) => 6,
+        (-1, 1) => 7,
+        _ => 8,
+    }
+}
@@ -0,0 +1,316 @@
+use crate::controller::outbox::Outbox;
+use crate::controller::{Controller, ControllerEvent};
+use anyhow::Result;
+use bitvec::prelude::*;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+// Button and axis index constants for port 1. Use `inputs::port` to address
+// the other ports.
+pub mod inputs {
+    pub const BUTTON_A: usize = 0;
+    pub const BUTTON_B: usize = 1;
+    pub const BUTTON_X: usize = 2;
+    pub const BUTTON_Y: usize = 3;
+    pub const BUTTON_LEFT: usize = 4;
+    pub const BUTTON_RIGHT: usize = 5;
+    pub const BUTTON_DOWN: usize = 6;
+    pub const BUTTON_UP: usize = 7;
+    pub const BUTTON_START: usize = 8;
+    pub const BUTTON_Z: usize = 9;
+    pub const BUTTON_R: usize = 10;
+    pub const BUTTON_L: usize = 11;
+
+    pub const AXIS_MAIN_X: usize = 0;
+    pub const AXIS_MAIN_Y: usize = 1;
+    pub const AXIS_C_X: usize = 2;
+    pub const AXIS_C_Y: usize = 3;
+    pub const AXIS_TRIGGER_L: usize = 4;
+    pub const AXIS_TRIGGER_R: usize = 5;
+
+    /// Distance between the indices of consecutive ports
+    pub const PORT_STRIDE: usize = 16;
+
+    /// Moves a button or axis index to the given port (0-3)
+    pub const fn port(port: usize, index: usize) -> usize {
+        port * PORT_STRIDE + index
+    }
+}
+
+pub const PORTS: usize = 4;
+
+/// Size of the 0x21 input report: the report id then 9 bytes per port
+const REPORT_SIZE: usize = 1 + PORTS * 9;
+
+/// How often the adapter sends input reports
+const REPORT_PERIOD: Duration = Duration::from_millis(8);
+
+#[derive(Debug, Clone, Copy)]
+struct Port {
+    connected: bool,
+    buttons: BitArr!(for 16, in Lsb0, u8),
+    axes: [u8; 6],
+}
+
+impl Default for Port {
+    fn default() -> Self {
+        Port {
+            connected: false,
+            buttons: BitArray::zeroed(),
+            axes: [0x80, 0x80, 0x80, 0x80, 0x00, 0x00],
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    ports: [Port; PORTS],
+    /// Set once the host sends the 0x13 init command
+    streaming: bool,
+    /// Whether the adapter's second plug, which powers rumble, is connected
+    rumble_power: bool,
+    rumble: [bool; PORTS],
+}
+
+impl State {
+    fn input_report(&self) -> Vec<u8> {
+        let mut report = Vec::with_capacity(REPORT_SIZE);
+        report.push(0x21);
+        for port in &self.ports {
+            let status = match (port.connected, self.rumble_power) {
+                (false, _) => 0x00,
+                (true, false) => 0x10,
+                (true, true) => 0x14,
+            };
+            report.push(status);
+            report.extend_from_slice(port.buttons.as_raw_slice());
+            report.extend_from_slice(&port.axes);
+        }
+        report
+    }
+}
+
+/// Handles an output report from the host, returning false if it wasn't
+/// recognised
+fn handle_output(buffer: &[u8], state: &mut State, event_tx: &SyncSender<ControllerEvent>) -> bool {
+    match buffer[0] {
+        0x13 => {
+            state.streaming = true;
+            true
+        }
+        0x11 if buffer.len() > PORTS => {
+            let mut rumble = [false; PORTS];
+            for (i, on) in rumble.iter_mut().enumerate() {
+                *on = buffer[1 + i] & 0x01 != 0;
+            }
+            if rumble != state.rumble {
+                state.rumble = rumble;
+                let _ = event_tx.try_send(ControllerEvent::GcRumble(rumble));
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The official GameCube controller adapter, with four ports that each take
+/// a controller with analog triggers and a C-stick. Buttons and axes are
+/// addressed per port with `inputs::port`.
+pub struct GcAdapter {
+    hid_path: PathBuf,
+    state: Arc<Mutex<State>>,
+    outbox: Option<Arc<Outbox>>,
+    protocol_thread_tx: Option<SyncSender<()>>,
+    report_thread_tx: Option<SyncSender<()>>,
+    event_tx: SyncSender<ControllerEvent>,
+    event_rx: Receiver<ControllerEvent>,
+}
+
+impl GcAdapter {
+    /// Creates an adapter with a controller plugged into port 1
+    pub fn create<P: AsRef<Path>>(path: P) -> GcAdapter {
+        let (event_tx, event_rx) = mpsc::sync_channel::<ControllerEvent>(10);
+        let mut ports = [Port::default(); PORTS];
+        ports[0].connected = true;
+        GcAdapter {
+            hid_path: path.as_ref().to_path_buf(),
+            state: Arc::new(Mutex::new(State {
+                ports,
+                streaming: false,
+                rumble_power: true,
+                rumble: [false; PORTS],
+            })),
+            outbox: None,
+            protocol_thread_tx: None,
+            report_thread_tx: None,
+            event_tx,
+            event_rx,
+        }
+    }
+
+    /// Plugs a controller into or out of a port (0-3)
+    pub fn set_connected(&mut self, port: usize, connected: bool) {
+        if let Some(port) = self.state().ports.get_mut(port) {
+            port.connected = connected;
+        }
+    }
+
+    pub fn set_rumble_power(&mut self, powered: bool) {
+        self.state().rumble_power = powered;
+    }
+
+    /// Whether the host has sent the init command and is reading input
+    pub fn is_streaming(&self) -> bool {
+        self.state().streaming
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn send_input(&self) -> Result<()> {
+        if let Some(outbox) = &self.outbox {
+            outbox.report(self.state().input_report());
+        }
+        Ok(())
+    }
+}
+
+impl Controller for GcAdapter {
+    type C = GcAdapter;
+
+    fn start_comms(&mut self) -> Result<()> {
+        let outbox = Arc::new(Outbox::default());
+        let (protocol_tx, protocol_rx) = mpsc::sync_channel(10);
+        let (report_tx, report_rx) = mpsc::sync_channel(1);
+        let hid_read = OpenOptions::new().read(true).open(&self.hid_path)?;
+        let hid_write = OpenOptions::new().write(true).open(&self.hid_path)?;
+        let state = self.state.clone();
+        let event_tx = self.event_tx.clone();
+
+        let mut buffer = [0; 64];
+        let mut reader = BufReader::new(hid_read);
+        let mut writer = BufWriter::new(hid_write);
+
+        // Thread for writing to the HID device
+        let writer_outbox = outbox.clone();
+        thread::spawn(move || {
+            while let Some(to_write) = writer_outbox.next() {
+                let _ = writer.write_all(&to_write);
+                let _ = writer.flush();
+            }
+        });
+
+        self.outbox = Some(outbox.clone());
+
+        // Thread for streaming input reports once the host has sent init
+        let report_state = self.state.clone();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = report_rx.recv_timeout(REPORT_PERIOD) {
+                let state = report_state.lock().unwrap();
+                if state.streaming {
+                    outbox.report(state.input_report());
+                }
+            }
+        });
+        self.report_thread_tx = Some(report_tx);
+
+        // Thread for handling init and rumble commands from the host
+        thread::spawn(move || loop {
+            match protocol_rx.try_recv() {
+                Ok(_) | Err(TryRecvError::Disconnected) => {
+                    break;
+                }
+                Err(TryRecvError::Empty) => {}
+            };
+
+            let read = reader.read(&mut buffer).unwrap_or(0);
+
+            if read == 0 {
+                continue;
+            }
+
+            let mut state = state.lock().unwrap();
+            if !handle_output(&buffer[..read], &mut state, &event_tx) {
+                let _ = event_tx.try_send(ControllerEvent::UnknownReport(buffer[..read].to_vec()));
+            }
+        });
+        self.protocol_thread_tx = Some(protocol_tx);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(protocol_thread_tx) = &self.protocol_thread_tx {
+            let _ = protocol_thread_tx.send(());
+        }
+        if let Some(report_thread_tx) = &self.report_thread_tx {
+            let _ = report_thread_tx.send(());
+        }
+        self.protocol_thread_tx = None;
+        self.report_thread_tx = None;
+        if let Some(outbox) = self.outbox.take() {
+            outbox.close();
+        }
+    }
+
+    fn set(&mut self, index: usize, value: bool, flush: bool) -> Result<()> {
+        {
+            let mut state = self.state();
+            let (port, index) = (index / inputs::PORT_STRIDE, index % inputs::PORT_STRIDE);
+            if let Some(port) = state.ports.get_mut(port) {
+                port.buttons.set(index, value);
+            }
+        }
+        if flush {
+            return self.send_input();
+        }
+        Ok(())
+    }
+
+    fn press(&mut self, index: usize, flush: bool) -> Result<()> {
+        self.set(index, true, flush)
+    }
+
+    fn release(&mut self, index: usize, flush: bool) -> Result<()> {
+        self.set(index, false, flush)
+    }
+
+    /// Axes take the same 16 bit range as the other controllers and are
+    /// reduced to the adapter's 8 bits. Triggers rest at 0, sticks at the
+    /// centre.
+    fn set_axis(&mut self, index: usize, value: u16, flush: bool) -> Result<()> {
+        {
+            let mut state = self.state();
+            let (port, index) = (index / inputs::PORT_STRIDE, index % inputs::PORT_STRIDE);
+            if let Some(axis) = state
+                .ports
+                .get_mut(port)
+                .and_then(|port| port.axes.get_mut(index))
+            {
+                *axis = (value >> 8) as u8;
+            }
+        }
+        if flush {
+            return self.send_input();
+        }
+        Ok(())
+    }
+
+    fn flush_input(&mut self) -> Result<()> {
+        self.send_input()
+    }
+
+    fn listen_for_events(&mut self) -> &Receiver<ControllerEvent> {
+        &self.event_rx
+    }
+
+    fn log_state(&self) {
+        log::debug!("{:?}", self.state().ports);
+    }
+}
@@ -0,0 +1,470 @@
+use crate::controller::outbox::Outbox;
+use crate::controller::{hat_switch, Controller, ControllerEvent};
+use crate::usb_gadget::hid::{generic_desktop, usage_page, ReportDescriptor, ReportKind};
+use anyhow::{bail, Result};
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+/// How often reports are sent
+const REPORT_PERIOD: Duration = Duration::from_millis(8);
+
+const HAT_DIRECTIONS: [&str; 4] = ["Up", "Right", "Down", "Left"];
+
+#[derive(Debug, Clone)]
+enum ButtonTarget {
+    /// A single bit at this offset in the report
+    Bit(usize),
+    /// One direction (up, right, down, left) of a hat switch
+    Hat(usize, usize),
+}
+
+#[derive(Debug, Clone)]
+struct Button {
+    name: String,
+    target: ButtonTarget,
+}
+
+#[derive(Debug, Clone)]
+struct Value {
+    name: String,
+    bit_offset: usize,
+    size: usize,
+    min: i32,
+    max: i32,
+}
+
+/// Where each control lives in the input report
+#[derive(Debug)]
+struct Layout {
+    report_id: u8,
+    len: usize,
+    buttons: Vec<Button>,
+    axes: Vec<Value>,
+    hats: Vec<Value>,
+}
+
+impl Layout {
+    /// Finds the buttons, axes and hat switches in the first input report
+    /// that has any
+    fn from_descriptor(desc: &ReportDescriptor) -> Result<Layout> {
+        for report_id in desc.report_ids(ReportKind::Input) {
+            let mut layout = Layout {
+                report_id,
+                len: desc.report_len(ReportKind::Input, report_id),
+                buttons: Vec::new(),
+                axes: Vec::new(),
+                hats: Vec::new(),
+            };
+            let fields = desc.report(ReportKind::Input, report_id);
+            for field in fields.filter(|f| !f.is_constant() && f.is_variable()) {
+                for i in 0..field.count {
+                    // Vendor defined usages aren't controls a player would use
+                    let usage = match field.usage(i) {
+                        Some(usage) if usage.page < 0xff00 => usage,
+                        _ => continue,
+                    };
+                    let bit_offset = field.bit_offset + i * field.size;
+                    let value = Value {
+                        name: usage.to_string(),
+                        bit_offset,
+                        size: field.size,
+                        min: field.logical_min,
+                        max: field.logical_max,
+                    };
+                    if usage.page == usage_page::GENERIC_DESKTOP
+                        && usage.id == generic_desktop::HAT_SWITCH
+                    {
+                        let hat = layout.hats.len();
+                        for (direction, name) in HAT_DIRECTIONS.iter().enumerate() {
+                            layout.add_button(
+                                format!("{} {}", value.name, name),
+                                ButtonTarget::Hat(hat, direction),
+                            );
+                        }
+                        layout.hats.push(value);
+                    } else if field.size == 1 {
+                        layout.add_button(value.name, ButtonTarget::Bit(bit_offset));
+                    } else {
+                        layout.add_axis(value);
+                    }
+                }
+            }
+            if !layout.buttons.is_empty() || !layout.axes.is_empty() {
+                return Ok(layout);
+            }
+        }
+        bail!("descriptor has no input report with buttons or axes");
+    }
+
+    /// Names repeat when a descriptor has e.g. two sticks both using X and
+    /// Y, so later ones are numbered
+    fn unique_name(&self, name: String) -> String {
+        let taken = |name: &str| {
+            self.buttons.iter().any(|b| b.name == name) || self.axes.iter().any(|a| a.name == name)
+        };
+        if !taken(&name) {
+            return name;
+        }
+        (2..)
+            .map(|n| format!("{} {}", name, n))
+            .find(|name| !taken(name))
+            .unwrap()
+    }
+
+    fn add_button(&mut self, name: String, target: ButtonTarget) {
+        let name = self.unique_name(name);
+        self.buttons.push(Button { name, target });
+    }
+
+    fn add_axis(&mut self, mut value: Value) {
+        value.name = self.unique_name(value.name);
+        self.axes.push(value);
+    }
+}
+
+/// Writes the low `size` bits of `value` at a bit offset, least significant
+/// bit first as HID reports are laid out
+fn pack(report: &mut [u8], bit_offset: usize, size: usize, value: i32) {
+    for i in 0..size.min(32) {
+        let bit = bit_offset + i;
+        if value >> i & 1 != 0 {
+            report[bit / 8] |= 1 << (bit % 8);
+        } else {
+            report[bit / 8] &= !(1 << (bit % 8));
+        }
+    }
+}
+
+/// The value a hat switch field takes for a d-pad direction from
+/// `hat_switch`. Hats with four positions only have the cardinal
+/// directions, so diagonals round anticlockwise. Neutral is a value outside
+/// the logical range, which is how hats report no direction; if the field
+/// has no room for one it's clamped to the range.
+fn hat_value(hat: &Value, direction: u8) -> i32 {
+    if direction < 8 {
+        let positions = hat.max as i64 - hat.min as i64 + 1;
+        let step = if positions == 4 {
+            direction / 2
+        } else {
+            direction
+        };
+        return hat.min + step as i32;
+    }
+    let largest = (1i64 << hat.size.min(32)) - 1;
+    if (hat.max as i64) < largest {
+        hat.max + 1
+    } else if hat.min > 0 {
+        hat.min - 1
+    } else {
+        hat.min
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    /// The report body, without the report ID
+    report: Vec<u8>,
+    hats: Vec<[bool; 4]>,
+}
+
+impl State {
+    fn input_report(&self, layout: &Layout) -> Vec<u8> {
+        if layout.report_id == 0 {
+            return self.report.clone();
+        }
+        [&[layout.report_id][..], &self.report].concat()
+    }
+}
+
+/// A gamepad built from any HID report descriptor. The buttons and axes of
+/// its first input report are named after their usages (e.g. "Button 1",
+/// "X", "Hat switch Up") and looked up with `button` and `axis`.
+pub struct HidGamepad {
+    hid_path: PathBuf,
+    layout: Arc<Layout>,
+    state: Arc<Mutex<State>>,
+    outbox: Option<Arc<Outbox>>,
+    report_thread_tx: Option<SyncSender<()>>,
+    _event_tx: SyncSender<ControllerEvent>,
+    event_rx: Receiver<ControllerEvent>,
+}
+
+impl HidGamepad {
+    pub fn create<P: AsRef<Path>>(path: P, report_desc: &[u8]) -> Result<HidGamepad> {
+        let layout = Layout::from_descriptor(&ReportDescriptor::parse(report_desc)?)?;
+        let (event_tx, event_rx) = mpsc::sync_channel::<ControllerEvent>(10);
+        let state = State {
+            report: vec![0; layout.len],
+            hats: vec![[false; 4]; layout.hats.len()],
+        };
+        let mut gamepad = HidGamepad {
+            hid_path: path.as_ref().to_path_buf(),
+            layout: Arc::new(layout),
+            state: Arc::new(Mutex::new(state)),
+            outbox: None,
+            report_thread_tx: None,
+            _event_tx: event_tx,
+            event_rx,
+        };
+        for index in 0..gamepad.layout.axes.len() {
+            gamepad.set_axis(index, 0x8000, false)?;
+        }
+        for hat in 0..gamepad.layout.hats.len() {
+            gamepad.update_hat(hat);
+        }
+        Ok(gamepad)
+    }
+
+    pub fn buttons(&self) -> Vec<&str> {
+        self.layout
+            .buttons
+            .iter()
+            .map(|b| b.name.as_str())
+            .collect()
+    }
+
+    pub fn axes(&self) -> Vec<&str> {
+        self.layout.axes.iter().map(|a| a.name.as_str()).collect()
+    }
+
+    /// The index of a button by name, for `set`, `press` and `release`
+    pub fn button(&self, name: &str) -> Option<usize> {
+        self.layout.buttons.iter().position(|b| b.name == name)
+    }
+
+    /// The index of an axis by name, for `set_axis`
+    pub fn axis(&self, name: &str) -> Option<usize> {
+        self.layout.axes.iter().position(|a| a.name == name)
+    }
+
+    fn update_hat(&mut self, hat: usize) {
+        let value = &self.layout.hats[hat];
+        let mut state = self.state();
+        let [up, right, down, left] = state.hats[hat];
+        let direction = hat_value(value, hat_switch(up, right, down, left));
+        pack(&mut state.report, value.bit_offset, value.size, direction);
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn send_input(&self) -> Result<()> {
+        if let Some(outbox) = &self.outbox {
+            outbox.report(self.state().input_report(&self.layout));
+        }
+        Ok(())
+    }
+}
+
+impl Controller for HidGamepad {
+    type C = HidGamepad;
+
+    fn start_comms(&mut self) -> Result<()> {
+        let outbox = Arc::new(Outbox::default());
+        let (report_tx, report_rx) = mpsc::sync_channel(1);
+        let mut hid_write = OpenOptions::new().write(true).open(&self.hid_path)?;
+
+        // Thread for writing to the HID device
+        let writer_outbox = outbox.clone();
+        thread::spawn(move || {
+            while let Some(to_write) = writer_outbox.next() {
+                let _ = hid_write.write_all(&to_write);
+            }
+        });
+
+        self.outbox = Some(outbox.clone());
+
+        // Thread for streaming input reports
+        let state = self.state.clone();
+        let layout = self.layout.clone();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = report_rx.recv_timeout(REPORT_PERIOD) {
+                let report = state.lock().unwrap().input_report(&layout);
+                outbox.report(report);
+            }
+        });
+        self.report_thread_tx = Some(report_tx);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(report_thread_tx) = &self.report_thread_tx {
+            let _ = report_thread_tx.send(());
+        }
+        self.report_thread_tx = None;
+        if let Some(outbox) = self.outbox.take() {
+            outbox.close();
+        }
+    }
+
+    fn set(&mut self, index: usize, value: bool, flush: bool) -> Result<()> {
+        match self.layout.buttons.get(index).map(|b| b.target.clone()) {
+            Some(ButtonTarget::Bit(bit_offset)) => {
+                pack(&mut self.state().report, bit_offset, 1, value as i32)
+            }
+            Some(ButtonTarget::Hat(hat, direction)) => {
+                self.state().hats[hat][direction] = value;
+                self.update_hat(hat);
+            }
+            None => (),
+        }
+        if flush {
+            return self.send_input();
+        }
+        Ok(())
+    }
+
+    fn press(&mut self, index: usize, flush: bool) -> Result<()> {
+        self.set(index, true, flush)
+    }
+
+    fn release(&mut self, index: usize, flush: bool) -> Result<()> {
+        self.set(index, false, flush)
+    }
+
+    /// Scales the 16 bit value onto the axis' logical range
+    fn set_axis(&mut self, index: usize, value: u16, flush: bool) -> Result<()> {
+        if let Some(axis) = self.layout.axes.get(index) {
+            let range = axis.max as i64 - axis.min as i64;
+            let scaled = axis.min as i64 + value as i64 * range / u16::MAX as i64;
+            pack(
+                &mut self.state().report,
+                axis.bit_offset,
+                axis.size,
+                scaled as i32,
+            );
+        }
+        if flush {
+            return self.send_input();
+        }
+        Ok(())
+    }
+
+    fn flush_input(&mut self) -> Result<()> {
+        self.send_input()
+    }
+
+    fn listen_for_events(&mut self) -> &Receiver<ControllerEvent> {
+        &self.event_rx
+    }
+
+    fn log_state(&self) {
+        log::debug!("{:02x?}", self.state().report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb_gadget::{hori_pad, ns_procon};
+
+    fn gamepad(report_desc: &[u8]) -> HidGamepad {
+        HidGamepad::create("/dev/null", report_desc).unwrap()
+    }
+
+    fn report(gamepad: &HidGamepad) -> Vec<u8> {
+        gamepad.state().input_report(&gamepad.layout)
+    }
+
+    fn hat(size: usize, min: i32, max: i32) -> Value {
+        Value {
+            name: "Hat switch".to_string(),
+            bit_offset: 0,
+            size,
+            min,
+            max,
+        }
+    }
+
+    #[test]
+    fn procon_layout() {
+        let mut procon = gamepad(&ns_procon::report_desc());
+        let buttons = procon.buttons();
+        assert_eq!(buttons.len(), 22);
+        assert_eq!(buttons[..2], ["Button 1", "Button 2"]);
+        assert_eq!(
+            buttons[14..18],
+            [
+                "Hat switch Up",
+                "Hat switch Right",
+                "Hat switch Down",
+                "Hat switch Left"
+            ]
+        );
+        assert_eq!(buttons[21], "Button 18");
+        assert_eq!(procon.axes(), ["X", "Y", "Z", "Rz"]);
+
+        // Report ID, then buttons, centred 16 bit sticks and a neutral hat
+        let mut expected = vec![0x30, 0x00, 0x00];
+        expected.extend_from_slice(&[0x00, 0x80].repeat(4));
+        expected.push(0x08);
+        expected.resize(64, 0x00);
+        assert_eq!(report(&procon), expected);
+
+        procon
+            .press(procon.button("Button 18").unwrap(), false)
+            .unwrap();
+        procon
+            .set_axis(procon.axis("Rz").unwrap(), 0xffff, false)
+            .unwrap();
+        expected[9..11].copy_from_slice(&[0xff, 0xff]);
+        expected[11] = 0x88;
+        assert_eq!(report(&procon), expected);
+    }
+
+    #[test]
+    fn hori_pad_layout() {
+        let mut pad = gamepad(&hori_pad::report_desc());
+        assert_eq!(pad.buttons().len(), 20);
+        assert_eq!(pad.buttons()[15], "Button 16");
+        assert_eq!(pad.buttons()[19], "Hat switch Left");
+        assert_eq!(pad.axes(), ["X", "Y", "Z", "Rz"]);
+        assert_eq!(
+            report(&pad),
+            [0x00, 0x00, 0x08, 0x7f, 0x7f, 0x7f, 0x7f, 0x00]
+        );
+
+        pad.press(pad.button("Button 2").unwrap(), false).unwrap();
+        pad.press(pad.button("Button 9").unwrap(), false).unwrap();
+        pad.set_axis(pad.axis("X").unwrap(), 0xffff, false).unwrap();
+        pad.set_axis(pad.axis("Y").unwrap(), 0, false).unwrap();
+        assert_eq!(
+            report(&pad),
+            [0x02, 0x01, 0x08, 0xff, 0x00, 0x7f, 0x7f, 0x00]
+        );
+    }
+
+    #[test]
+    fn hat_directions() {
+        let mut pad = gamepad(&hori_pad::report_desc());
+        let up = pad.button("Hat switch Up").unwrap();
+        let left = pad.button("Hat switch Left").unwrap();
+        let hat = |pad: &HidGamepad| report(pad)[2];
+
+        pad.press(up, false).unwrap();
+        assert_eq!(hat(&pad), 0);
+        pad.press(left, false).unwrap();
+        assert_eq!(hat(&pad), 7);
+        pad.release(up, false).unwrap();
+        assert_eq!(hat(&pad), 6);
+        pad.release(left, false).unwrap();
+        assert_eq!(hat(&pad), 8);
+    }
+
+    #[test]
+    fn four_way_hats() {
+        // Up, up-right, right and neutral
+        let directions = [0, 1, 2, 8];
+        let values = |hat: &Value| directions.map(|d| hat_value(hat, d));
+        assert_eq!(values(&hat(3, 0, 3)), [0, 0, 1, 4]);
+        // No value out of range fits, so neutral is clamped
+        assert_eq!(values(&hat(2, 0, 3)), [0, 0, 1, 0]);
+        assert_eq!(values(&hat(4, 0, 7)), [0, 1, 2, 8]);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::controller::outbox::Outbox;
+use crate::controller::{hat_switch, Controller, ControllerEvent};
+use anyhow::Result;
+use bitvec::prelude::*;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+// Button index constants
+pub mod inputs {
+    pub const BUTTON_Y: usize = 0;
+    pub const BUTTON_B: usize = 1;
+    pub const BUTTON_A: usize = 2;
+    pub const BUTTON_X: usize = 3;
+    pub const BUTTON_L: usize = 4;
+    pub const BUTTON_R: usize = 5;
+    pub const BUTTON_ZL: usize = 6;
+    pub const BUTTON_ZR: usize = 7;
+    pub const BUTTON_MINUS: usize = 8;
+    pub const BUTTON_PLUS: usize = 9;
+    pub const BUTTON_L_STICK: usize = 10;
+    pub const BUTTON_R_STICK: usize = 11;
+    pub const BUTTON_HOME: usize = 12;
+    pub const BUTTON_CAPTURE: usize = 13;
+    pub const BUTTON_UP: usize = 16;
+    pub const BUTTON_RIGHT: usize = 17;
+    pub const BUTTON_DOWN: usize = 18;
+    pub const BUTTON_LEFT: usize = 19;
+
+    pub const AXIS_LH: usize = 0;
+    pub const AXIS_LV: usize = 1;
+    pub const AXIS_RH: usize = 2;
+    pub const AXIS_RV: usize = 3;
+}
+
+/// How often reports are sent, matching the pad's 8 ms polling interval
+const REPORT_PERIOD: Duration = Duration::from_millis(8);
+
+#[derive(Debug)]
+struct State {
+    /// Bits 0-13 are the buttons as laid out in the report, 16-19 the d-pad
+    buttons: BitArr!(for 20, in Lsb0, u8),
+    axes: [u8; 4],
+}
+
+impl State {
+    /// The 8 byte input report: buttons, hat switch, four stick axes and a
+    /// vendor byte
+    fn input_report(&self) -> Vec<u8> {
+        let buttons: u16 = self.buttons[..14].load_le();
+        let hat = hat_switch(
+            self.buttons[inputs::BUTTON_UP],
+            self.buttons[inputs::BUTTON_RIGHT],
+            self.buttons[inputs::BUTTON_DOWN],
+            self.buttons[inputs::BUTTON_LEFT],
+        );
+        let mut report = buttons.to_le_bytes().to_vec();
+        report.push(hat);
+        report.extend_from_slice(&self.axes);
+        report.push(0x00);
+        report
+    }
+}
+
+/// Writes reports to the HID device until the outbox is closed. Writes fail
+/// until the host has configured the gadget, so the first one to succeed
+/// means the Switch is reading input.
+fn write_reports<W: Write>(
+    mut hid_write: W,
+    outbox: &Outbox,
+    event_tx: &SyncSender<ControllerEvent>,
+) {
+    let mut active = false;
+    while let Some(to_write) = outbox.next() {
+        if hid_write.write_all(&to_write).is_ok() && !active {
+            active = true;
+            let _ = event_tx.try_send(ControllerEvent::InputActive);
+        }
+    }
+}
+
+/// A HORI wired pad (as used by Pokkén Tournament DX). The Switch treats it
+/// as a plain HID gamepad, so there is no handshake: reports are streamed
+/// from the moment comms start.
+pub struct HoriPad {
+    hid_path: PathBuf,
+    state: Arc<Mutex<State>>,
+    outbox: Option<Arc<Outbox>>,
+    report_thread_tx: Option<SyncSender<()>>,
+    event_tx: SyncSender<ControllerEvent>,
+    event_rx: Receiver<ControllerEvent>,
+}
+
+impl HoriPad {
+    pub fn create<P: AsRef<Path>>(path: P) -> HoriPad {
+        let (event_tx, event_rx) = mpsc::sync_channel::<ControllerEvent>(10);
+        HoriPad {
+            hid_path: path.as_ref().to_path_buf(),
+            state: Arc::new(Mutex::new(State {
+                buttons: BitArray::zeroed(),
+                axes: [0x80; 4],
+            })),
+            outbox: None,
+            report_thread_tx: None,
+            event_tx,
+            event_rx,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn send_input(&self) -> Result<()> {
+        if let Some(outbox) = &self.outbox {
+            outbox.report(self.state().input_report());
+        }
+        Ok(())
+    }
+}
+
+impl Controller for HoriPad {
+    type C = HoriPad;
+
+    fn start_comms(&mut self) -> Result<()> {
+        let outbox = Arc::new(Outbox::default());
+        let (report_tx, report_rx) = mpsc::sync_channel(1);
+        let hid_write = OpenOptions::new().write(true).open(&self.hid_path)?;
+
+        // Thread for writing to the HID device
+        let writer_outbox = outbox.clone();
+        let event_tx = self.event_tx.clone();
+        thread::spawn(move || write_reports(hid_write, &writer_outbox, &event_tx));
+
+        self.outbox = Some(outbox.clone());
+
+        // Thread for streaming input reports
+        let state = self.state.clone();
+        thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = report_rx.recv_timeout(REPORT_PERIOD) {
+                let report = state.lock().unwrap().input_report();
+                outbox.report(report);
+            }
+        });
+        self.report_thread_tx = Some(report_tx);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        if let Some(report_thread_tx) = &self.report_thread_tx {
+            let _ = report_thread_tx.send(());
+        }
+        self.report_thread_tx = None;
+        if let Some(outbox) = self.outbox.take() {
+            outbox.close();
+        }
+    }
+
+    fn set(&mut self, index: usize, value: bool, flush: bool) -> Result<()> {
+        if let Some(mut button) = self.state().buttons.get_mut(index) {
+            *button = value;
+        }
+        if flush {
+            return self.send_input();
+        }
+        Ok(())
+    }
+
+    fn press(&mut self, index: usize, flush: bool) -> Result<()> {
+        self.set(index, true, flush)
+    }
+
+    fn release(&mut self, index: usize, flush: bool) -> Result<()> {
+        self.set(index, false, flush)
+    }
+
+    /// Axes take the same 16 bit range as the other controllers and are
+    /// reduced to the pad's 8 bits
+    fn set_axis(&mut self, index: usize, value: u16, flush: bool) -> Result<()> {
+        if let Some(axis) = self.state().axes.get_mut(index) {
+            *axis = (value >> 8) as u8;
+        }
+        if flush {
+            return self.send_input();
+        }
+        Ok(())
+    }
+
+    fn flush_input(&mut self) -> Result<()> {
+        self.send_input()
+    }
+
+    fn listen_for_events(&mut self) -> &Receiver<ControllerEvent> {
+        &self.event_rx
+    }
+
+    fn log_state(&self) {
+        log::debug!("{:?}", *self.state());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// Fails a number of writes, like the HID device does before the host
+    /// has configured the gadget, then records what's written
+    struct UnconfiguredHid {
+        failures: usize,
+        written: Vec<Vec<u8>>,
+    }
+
+    impl Write for UnconfiguredHid {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::other("not configured"));
+            }
+            self.written.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn input_active_after_first_write() {
+        let outbox = Outbox::default();
+        let (event_tx, event_rx) = mpsc::sync_channel(10);
+        let mut hid = UnconfiguredHid {
+            failures: 3,
+            written: Vec::new(),
+        };
+        thread::scope(|scope| {
+            scope.spawn(|| write_reports(&mut hid, &outbox, &event_tx));
+            for i in 0..4 {
+                outbox.reply(vec![i]);
+            }
+            let event = event_rx.recv_timeout(Duration::from_secs(1));
+            assert!(matches!(event, Ok(ControllerEvent::InputActive)));
+
+            outbox.reply(vec![4]);
+            thread::sleep(REPORT_PERIOD * 5);
+            outbox.close();
+        });
+        assert_eq!(hid.written, [[3], [4]]);
+        // Only the first successful write is an event
+        assert!(event_rx.try_recv().is_err());
+    }
+}
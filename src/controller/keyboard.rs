@@ -0,0 +1,395 @@
+use crate::controller::{Controller, ControllerEvent};
+use anyhow::{bail, Result};
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+// Key index constants, which are the HID keyboard usage IDs
+pub mod inputs {
+    pub const KEY_A: usize = 0x04;
+    pub const KEY_B: usize = 0x05;
+    pub const KEY_C: usize = 0x06;
+    pub const KEY_D: usize = 0x07;
+    pub const KEY_E: usize = 0x08;
+    pub const KEY_F: usize = 0x09;
+    pub const KEY_G: usize = 0x0a;
+    pub const KEY_H: usize = 0x0b;
+    pub const KEY_I: usize = 0x0c;
+    pub const KEY_J: usize = 0x0d;
+    pub const KEY_K: usize = 0x0e;
+    pub const KEY_L: usize = 0x0f;
+    pub const KEY_M: usize = 0x10;
+    pub const KEY_N: usize = 0x11;
+    pub const KEY_O: usize = 0x12;
+    pub const KEY_P: usize = 0x13;
+    pub const KEY_Q: usize = 0x14;
+    pub const KEY_R: usize = 0x15;
+    pub const KEY_S: usize = 0x16;
+    pub const KEY_T: usize = 0x17;
+    pub const KEY_U: usize = 0x18;
+    pub const KEY_V: usize = 0x19;
+    pub const KEY_W: usize = 0x1a;
+    pub const KEY_X: usize = 0x1b;
+    pub const KEY_Y: usize = 0x1c;
+    pub const KEY_Z: usize = 0x1d;
+    pub const KEY_1: usize = 0x1e;
+    pub const KEY_2: usize = 0x1f;
+    pub const KEY_3: usize = 0x20;
+    pub const KEY_4: usize = 0x21;
+    pub const KEY_5: usize = 0x22;
+    pub const KEY_6: usize = 0x23;
+    pub const KEY_7: usize = 0x24;
+    pub const KEY_8: usize = 0x25;
+    pub const KEY_9: usize = 0x26;
+    pub const KEY_0: usize = 0x27;
+    pub const KEY_ENTER: usize = 0x28;
+    pub const KEY_ESCAPE: usize = 0x29;
+    pub const KEY_BACKSPACE: usize = 0x2a;
+    pub const KEY_TAB: usize = 0x2b;
+    pub const KEY_SPACE: usize = 0x2c;
+    pub const KEY_MINUS: usize = 0x2d;
+    pub const KEY_EQUAL: usize = 0x2e;
+    pub const KEY_LEFT_BRACE: usize = 0x2f;
+    pub const KEY_RIGHT_BRACE: usize = 0x30;
+    pub const KEY_BACKSLASH: usize = 0x31;
+    pub const KEY_SEMICOLON: usize = 0x33;
+    pub const KEY_APOSTROPHE: usize = 0x34;
+    pub const KEY_GRAVE: usize = 0x35;
+    pub const KEY_COMMA: usize = 0x36;
+    pub const KEY_DOT: usize = 0x37;
+    pub const KEY_SLASH: usize = 0x38;
+    pub const KEY_CAPS_LOCK: usize = 0x39;
+    pub const KEY_F1: usize = 0x3a;
+    pub const KEY_F2: usize = 0x3b;
+    pub const KEY_F3: usize = 0x3c;
+    pub const KEY_F4: usize = 0x3d;
+    pub const KEY_F5: usize = 0x3e;
+    pub const KEY_F6: usize = 0x3f;
+    pub const KEY_F7: usize = 0x40;
+    pub const KEY_F8: usize = 0x41;
+    pub const KEY_F9: usize = 0x42;
+    pub const KEY_F10: usize = 0x43;
+    pub const KEY_F11: usize = 0x44;
+    pub const KEY_F12: usize = 0x45;
+    pub const KEY_INSERT: usize = 0x49;
+    pub const KEY_HOME: usize = 0x4a;
+    pub const KEY_PAGE_UP: usize = 0x4b;
+    pub const KEY_DELETE: usize = 0x4c;
+    pub const KEY_END: usize = 0x4d;
+    pub const KEY_PAGE_DOWN: usize = 0x4e;
+    pub const KEY_RIGHT: usize = 0x4f;
+    pub const KEY_LEFT: usize = 0x50;
+    pub const KEY_DOWN: usize = 0x51;
+    pub const KEY_UP: usize = 0x52;
+
+    pub const KEY_LEFT_CTRL: usize = 0xe0;
+    pub const KEY_LEFT_SHIFT: usize = 0xe1;
+    pub const KEY_LEFT_ALT: usize = 0xe2;
+    pub const KEY_LEFT_META: usize = 0xe3;
+    pub const KEY_RIGHT_CTRL: usize = 0xe4;
+    pub const KEY_RIGHT_SHIFT: usize = 0xe5;
+    pub const KEY_RIGHT_ALT: usize = 0xe6;
+    pub const KEY_RIGHT_META: usize = 0xe7;
+}
+
+/// Number of non-modifier keys a boot keyboard report can hold at once
+const MAX_KEYS: usize = 6;
+
+/// Highest key usage the gadget's report descriptor allows
+const MAX_KEY: usize = 0x65;
+
+/// Looks up the key for a character on a US layout, and whether shift has
+/// to be held to type it
+fn key_for_char(c: char) -> Option<(usize, bool)> {
+    use inputs::*;
+
+    let key = match c {
+        'a'..='z' => (KEY_A + (c as usize - 'a' as usize), false),
+        'A'..='Z' => (KEY_A + (c as usize - 'A' as usize), true),
+        '1'..='9' => (KEY_1 + (c as usize - '1' as usize), false),
+        '0' => (KEY_0, false),
+        '!' => (KEY_1, true),
+        '@' => (KEY_2, true),
+        '#' => (KEY_3, true),
+        '$' => (KEY_4, true),
+        '%' => (KEY_5, true),
+        '^' => (KEY_6, true),
+        '&' => (KEY_7, true),
+        '*' => (KEY_8, true),
+        '(' => (KEY_9, true),
+        ')' => (KEY_0, true),
+        '\n' => (KEY_ENTER, false),
+        '\t' => (KEY_TAB, false),
+        ' ' => (KEY_SPACE, false),
+        '-' => (KEY_MINUS, false),
+        '_' => (KEY_MINUS, true),
+        '=' => (KEY_EQUAL, false),
+        '+' => (KEY_EQUAL, true),
+        '[' => (KEY_LEFT_BRACE, false),
+        '{' => (KEY_LEFT_BRACE, true),
+        ']' => (KEY_RIGHT_BRACE, false),
+        '}' => (KEY_RIGHT_BRACE, true),
+        '\\' => (KEY_BACKSLASH, false),
+        '|' => (KEY_BACKSLASH, true),
+        ';' => (KEY_SEMICOLON, false),
+        ':' => (KEY_SEMICOLON, true),
+        '\'' => (KEY_APOSTROPHE, false),
+        '"' => (KEY_APOSTROPHE, true),
+        '`' => (KEY_GRAVE, false),
+        '~' => (KEY_GRAVE, true),
+        ',' => (KEY_COMMA, false),
+        '<' => (KEY_COMMA, true),
+        '.' => (KEY_DOT, false),
+        '>' => (KEY_DOT, true),
+        '/' => (KEY_SLASH, false),
+        '?' => (KEY_SLASH, true),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// A USB boot protocol keyboard. Indices passed to `set`, `press` and
+/// `release` are HID usage IDs from `inputs`; the modifier keys are folded
+/// into the report's modifier byte.
+pub struct Keyboard {
+    hid_path: PathBuf,
+    modifiers: u8,
+    keys: Vec<u8>,
+    hid_thread_tx: Option<SyncSender<Vec<u8>>>,
+    /// Keyboards raise no events, but holding the sender keeps the channel
+    /// open for anyone listening
+    _event_tx: SyncSender<ControllerEvent>,
+    event_rx: Receiver<ControllerEvent>,
+}
+
+impl Keyboard {
+    pub fn create<P: AsRef<Path>>(path: P) -> Keyboard {
+        let (event_tx, event_rx) = mpsc::sync_channel::<ControllerEvent>(10);
+        Keyboard {
+            hid_path: path.as_ref().to_path_buf(),
+            modifiers: 0,
+            keys: Vec::with_capacity(MAX_KEYS),
+            hid_thread_tx: None,
+            _event_tx: event_tx,
+            event_rx,
+        }
+    }
+
+    /// Types a string by pressing and releasing the key for each character,
+    /// holding shift where needed. Fails without typing anything if the
+    /// string has characters a US keyboard can't type.
+    pub fn type_text(&mut self, text: &str) -> Result<()> {
+        let mut keys = Vec::with_capacity(text.len());
+        for c in text.chars() {
+            match key_for_char(c) {
+                Some(key) => keys.push(key),
+                None => bail!("can't type {:?} on a US keyboard", c),
+            }
+        }
+
+        // Held keys would combine with the typed ones, so they're released
+        // while typing and pressed again afterwards
+        let modifiers = self.modifiers;
+        let held = std::mem::take(&mut self.keys);
+        let typed = (|| {
+            for (key, shift) in keys {
+                self.modifiers = 0;
+                self.set_key(inputs::KEY_LEFT_SHIFT, shift);
+                self.set_key(key, true);
+                self.send_input_blocking()?;
+                self.set_key(key, false);
+                self.send_input_blocking()?;
+            }
+            Ok(())
+        })();
+        // Restored even if typing failed, so the held keys aren't lost
+        self.modifiers = modifiers;
+        self.keys = held;
+        typed.and_then(|()| self.send_input_blocking())
+    }
+
+    /// Returns false if there's no key with this usage
+    fn set_key(&mut self, index: usize, value: bool) -> bool {
+        match index {
+            inputs::KEY_LEFT_CTRL..=inputs::KEY_RIGHT_META => {
+                let bit = 1 << (index - inputs::KEY_LEFT_CTRL);
+                if value {
+                    self.modifiers |= bit;
+                } else {
+                    self.modifiers &= !bit;
+                }
+            }
+            0x04..=MAX_KEY => {
+                let key = index as u8;
+                if !value {
+                    self.keys.retain(|k| *k != key);
+                } else if !self.keys.contains(&key) && self.keys.len() < MAX_KEYS {
+                    self.keys.push(key);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn input_report(&self) -> Vec<u8> {
+        let mut report = vec![self.modifiers, 0x00];
+        report.extend_from_slice(&self.keys);
+        report.resize(2 + MAX_KEYS, 0x00);
+        report
+    }
+
+    fn send_input(&self) -> Result<()> {
+        if let Some(hid_tx) = &self.hid_thread_tx {
+            hid_tx.try_send(self.input_report())?
+        }
+        Ok(())
+    }
+
+    /// Like `send_input`, but waits for room in the queue so no key strokes
+    /// are dropped
+    fn send_input_blocking(&self) -> Result<()> {
+        if let Some(hid_tx) = &self.hid_thread_tx {
+            hid_tx.send(self.input_report())?
+        }
+        Ok(())
+    }
+}
+
+impl Controller for Keyboard {
+    type C = Keyboard;
+
+    fn start_comms(&mut self) -> Result<()> {
+        let (hid_tx, hid_rx) = mpsc::sync_channel::<Vec<u8>>(10);
+        let mut hid_write = OpenOptions::new().write(true).open(&self.hid_path)?;
+
+        // Thread for writing to the HID device
+        thread::spawn(move || {
+            for to_write in hid_rx {
+                let _ = hid_write.write_all(&to_write);
+            }
+        });
+
+        self.hid_thread_tx = Some(hid_tx);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.hid_thread_tx = None;
+    }
+
+    fn set(&mut self, index: usize, value: bool, flush: bool) -> Result<()> {
+        if !self.set_key(index, value) {
+            bail!("no key with usage {:#04x}", index);
+        }
+        if flush {
+            return self.send_input();
+        }
+        Ok(())
+    }
+
+    fn press(&mut self, index: usize, flush: bool) -> Result<()> {
+        self.set(index, true, flush)
+    }
+
+    fn release(&mut self, index: usize, flush: bool) -> Result<()> {
+        self.set(index, false, flush)
+    }
+
+    /// Keyboards have no axes, so this does nothing
+    fn set_axis(&mut self, _index: usize, _value: u16, _flush: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn flush_input(&mut self) -> Result<()> {
+        self.send_input()
+    }
+
+    fn listen_for_events(&mut self) -> &Receiver<ControllerEvent> {
+        &self.event_rx
+    }
+
+    fn log_state(&self) {
+        log::debug!("modifiers {:#04x} keys {:02x?}", self.modifiers, self.keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A keyboard whose reports go to the returned receiver
+    fn keyboard() -> (Keyboard, Receiver<Vec<u8>>) {
+        let mut keyboard = Keyboard::create("/dev/null");
+        let (hid_tx, hid_rx) = mpsc::sync_channel(100);
+        keyboard.hid_thread_tx = Some(hid_tx);
+        (keyboard, hid_rx)
+    }
+
+    #[test]
+    fn shifted_symbols() {
+        use inputs::*;
+
+        assert_eq!(key_for_char('a'), Some((KEY_A, false)));
+        assert_eq!(key_for_char('Z'), Some((KEY_Z, true)));
+        assert_eq!(key_for_char('0'), Some((KEY_0, false)));
+        assert_eq!(key_for_char(')'), Some((KEY_0, true)));
+        assert_eq!(key_for_char('@'), Some((KEY_2, true)));
+        assert_eq!(key_for_char('"'), Some((KEY_APOSTROPHE, true)));
+        assert_eq!(key_for_char('?'), Some((KEY_SLASH, true)));
+        assert_eq!(key_for_char('\n'), Some((KEY_ENTER, false)));
+    }
+
+    #[test]
+    fn unsupported_characters() {
+        assert_eq!(key_for_char('é'), None);
+        assert_eq!(key_for_char('£'), None);
+
+        let (mut keyboard, hid_rx) = keyboard();
+        assert!(keyboard.type_text("abcé").is_err());
+        // Nothing is typed
+        assert!(hid_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn types_text() {
+        let (mut keyboard, hid_rx) = keyboard();
+        keyboard.type_text("a!").unwrap();
+        let reports: Vec<_> = hid_rx.try_iter().collect();
+        assert_eq!(
+            reports,
+            [
+                [0x00, 0x00, 0x04, 0, 0, 0, 0, 0],
+                [0x00, 0x00, 0x00, 0, 0, 0, 0, 0],
+                [0x02, 0x00, 0x1e, 0, 0, 0, 0, 0],
+                [0x02, 0x00, 0x00, 0, 0, 0, 0, 0],
+                [0x00, 0x00, 0x00, 0, 0, 0, 0, 0],
+            ]
+        );
+    }
+
+    #[test]
+    fn held_keys_are_restored() {
+        let (mut keyboard, hid_rx) = keyboard();
+        keyboard.press(inputs::KEY_LEFT_CTRL, false).unwrap();
+        keyboard.press(inputs::KEY_B, false).unwrap();
+        keyboard.type_text("A").unwrap();
+        let reports: Vec<_> = hid_rx.try_iter().collect();
+        // Shift alone while typing, then ctrl+b again
+        assert_eq!(reports[0], [0x02, 0x00, 0x04, 0, 0, 0, 0, 0]);
+        assert_eq!(reports[2], [0x01, 0x00, 0x05, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn held_keys_are_restored_on_failure() {
+        let (mut keyboard, hid_rx) = keyboard();
+        keyboard.press(inputs::KEY_LEFT_CTRL, false).unwrap();
+        keyboard.press(inputs::KEY_B, false).unwrap();
+        drop(hid_rx);
+        assert!(keyboard.type_text("abc").is_err());
+        assert_eq!(keyboard.modifiers, 0x01);
+        assert_eq!(keyboard.keys, [0x05]);
+    }
+}
@@ -1,14 +1,38 @@
-use crate::controller::{Controller, ControllerEvent};
-use anyhow::Result;
+use crate::controller::outbox::Outbox;
+use crate::controller::{hat_switch, Controller, ControllerEvent, HandshakeStage};
+use anyhow::{bail, Result};
 use bitvec::prelude::*;
 use rand::Rng;
+use std::collections::{HashMap, VecDeque};
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::mpsc::{
+    self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError,
+};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
+
+mod battery;
+mod device;
+mod imu;
+mod lights;
+mod mcu;
+pub mod nso;
+mod ntag;
+mod rumble;
+mod spi_flash;
+
+use battery::Battery;
+pub use battery::{BatteryLevel, PowerSource};
+pub use device::{DeviceType, Orientation};
+use imu::Imu;
+pub use imu::ImuSample;
+use mcu::Mcu;
+pub use ntag::Ntag;
+pub use spi_flash::SpiFlash;
 
 // Button index constants
 pub mod inputs {
@@ -26,7 +50,7 @@ pub mod inputs {
     pub const BUTTON_L_STICK: usize = 11;
     pub const BUTTON_HOME: usize = 12;
     pub const BUTTON_CAPTURE: usize = 13;
-    pub const BUTTON_CHARGING_GRIP: usize = 5;
+    pub const BUTTON_CHARGING_GRIP: usize = 15;
     pub const BUTTON_DOWN: usize = 16;
     pub const BUTTON_UP: usize = 17;
     pub const BUTTON_RIGHT: usize = 18;
@@ -44,27 +68,191 @@ pub mod inputs {
 
 mod magic {
     pub const INITIAL_INPUT: [u8; 9] = [0x00, 0x80, 0x00, 0xf8, 0xd7, 0x7a, 0x22, 0xc8, 0x7b];
-    pub const SERIAL_NUMBER: [u8; 16] = [0xff; 16];
-    pub const SENSOR_STICK_PARAMS: [u8; 24] = [
-        0x50, 0xfd, 0x00, 0x00, 0xc6, 0x0f, 0x0f, 0x30, 0x61, 0x96, 0x30, 0xf3, 0xd4, 0x14, 0x54,
-        0x41, 0x15, 0x54, 0xc7, 0x79, 0x9c, 0x33, 0x36, 0x63,
-    ];
-    pub const STICK_PARAMS_2: [u8; 18] = [
-        0x0f, 0x30, 0x61, 0x96, 0x30, 0xf3, 0xd4, 0x14, 0x54, 0x41, 0x15, 0x54, 0xc7, 0x79, 0x9c,
-        0x33, 0x36, 0x63,
-    ];
-    pub const CONFIG: [u8; 25] = [
-        0xba, 0x15, 0x62, 0x11, 0xb8, 0x7f, 0x29, 0x06, 0x5b, 0xff, 0xe7, 0x7e, 0x0e, 0x36, 0x56,
-        0x9e, 0x85, 0x60, 0xff, 0x32, 0x32, 0x32, 0xff, 0xff, 0xff,
-    ];
-    pub const CALIBRATION: [u8; 24] = [
-        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
-        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xb2, 0xa1,
-    ];
-    pub const SENSOR_CALIBRATION: [u8; 24] = [
-        0xbe, 0xff, 0x3e, 0x00, 0xf0, 0x01, 0x00, 0x40, 0x00, 0x40, 0x00, 0x40, 0xfe, 0xff, 0xfe,
-        0xff, 0x08, 0x00, 0xe7, 0x3b, 0xe7, 0x3b, 0xe7, 0x3b,
-    ];
+}
+
+/// Longest SPI read the Switch will ask for in a single subcommand
+const SPI_MAX_READ: u8 = 0x1d;
+
+/// Input report formats the Switch can select with subcommand 0x03
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportMode {
+    /// Standard full mode (0x30): buttons, sticks and IMU, streamed
+    /// periodically
+    Full,
+    /// NFC/IR mode (0x31): full mode followed by an MCU payload. These
+    /// reports are 362 bytes long, so the mode is refused unless the HID
+    /// function's report length allows for them (see
+    /// `NsProcon::with_report_length`).
+    NfcIr,
+    /// Simple HID mode (0x3F): packed buttons, hat and sticks, sent only
+    /// when the input changes
+    SimpleHid,
+}
+
+impl ReportMode {
+    fn from_id(id: u8) -> Option<ReportMode> {
+        match id {
+            0x30 => Some(ReportMode::Full),
+            0x31 => Some(ReportMode::NfcIr),
+            0x3f => Some(ReportMode::SimpleHid),
+            _ => None,
+        }
+    }
+
+    /// Length of an input report in this mode
+    pub fn report_len(self) -> usize {
+        match self {
+            ReportMode::Full | ReportMode::SimpleHid => 64,
+            ReportMode::NfcIr => NFC_IR_HEADER_LEN + mcu::REPORT_SIZE,
+        }
+    }
+}
+
+/// How much of a full mode report comes before the MCU payload in NFC/IR
+/// mode
+const NFC_IR_HEADER_LEN: usize = 49;
+
+/// Report length of the HID functions in the Switch controller gadget
+/// presets, as on a real Pro Controller
+const DEFAULT_REPORT_LENGTH: usize = 64;
+
+/// How to answer a subcommand the emulator has no built-in handling for
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubcommandReply {
+    /// Plain ack (0x80) with no data, the default
+    Ack,
+    /// Reply with the given ack byte and data
+    Data { ack: u8, data: Vec<u8> },
+    /// Send nothing
+    Ignore,
+}
+
+/// How often events waiting for room in the event channel are retried
+const EVENT_RETRY_PERIOD: Duration = Duration::from_millis(10);
+
+/// How often a real Pro Controller sends input reports over USB
+const DEFAULT_REPORT_PERIOD: Duration = Duration::from_millis(8);
+
+/// State shared between the `NsProcon` handle and its protocol and
+/// reporting threads
+#[derive(Debug)]
+struct State {
+    device_type: DeviceType,
+    orientation: Orientation,
+    input_state: BitArr!(for 72, in Lsb0, u8),
+    spi_flash: SpiFlash,
+    imu: Imu,
+    report_mode: ReportMode,
+    mcu: Mcu,
+    battery: Battery,
+    handshake: Option<HandshakeStage>,
+    subcommand_replies: HashMap<u8, SubcommandReply>,
+    report_period: Duration,
+    /// Longest report the HID function can carry
+    report_length: usize,
+}
+
+impl State {
+    fn create(body_col: [u8; 3]) -> State {
+        State {
+            device_type: DeviceType::ProController,
+            orientation: Orientation::Vertical,
+            input_state: BitArray::zeroed(),
+            spi_flash: SpiFlash::create(body_col),
+            imu: Imu::default(),
+            report_mode: ReportMode::Full,
+            mcu: Mcu::default(),
+            battery: Battery::default(),
+            handshake: None,
+            subcommand_replies: HashMap::new(),
+            report_period: DEFAULT_REPORT_PERIOD,
+            report_length: DEFAULT_REPORT_LENGTH,
+        }
+    }
+
+    fn battery_status(&mut self) -> u8 {
+        self.battery.status() | self.device_type.connection_info()
+    }
+
+    fn is_streaming(&self) -> bool {
+        let streaming = match self.handshake {
+            None
+            | Some(HandshakeStage::Status)
+            | Some(HandshakeStage::Handshake)
+            | Some(HandshakeStage::BaudRate)
+            | Some(HandshakeStage::Suspended) => false,
+            Some(HandshakeStage::HidOnly)
+            | Some(HandshakeStage::DeviceInfo)
+            | Some(HandshakeStage::InputMode)
+            | Some(HandshakeStage::Active) => true,
+        };
+        streaming && self.report_mode != ReportMode::SimpleHid
+    }
+
+    fn input_report(&mut self) -> Vec<u8> {
+        match self.report_mode {
+            ReportMode::Full => self.full_report(0x30),
+            ReportMode::NfcIr => {
+                let mut input_msg = self.full_report(0x31);
+                input_msg.truncate(NFC_IR_HEADER_LEN);
+                input_msg.extend_from_slice(&self.mcu.report());
+                input_msg
+            }
+            ReportMode::SimpleHid => self.simple_report(),
+        }
+    }
+
+    fn full_report(&mut self, id: u8) -> Vec<u8> {
+        let mut input_msg = vec![id, timestamp(), self.battery_status()];
+        input_msg.extend_from_slice(self.input_state.as_buffer());
+        input_msg.push(0x00);
+        input_msg.extend_from_slice(&self.imu.frames());
+        input_msg.resize(64, 0);
+        input_msg
+    }
+
+    fn simple_report(&self) -> Vec<u8> {
+        use inputs::*;
+
+        let pressed = |index: usize| self.input_state[index];
+        let buttons = [
+            BUTTON_B,
+            BUTTON_A,
+            BUTTON_Y,
+            BUTTON_X,
+            BUTTON_L,
+            BUTTON_R,
+            BUTTON_ZL,
+            BUTTON_ZR,
+            BUTTON_MINUS,
+            BUTTON_PLUS,
+            BUTTON_L_STICK,
+            BUTTON_R_STICK,
+            BUTTON_HOME,
+            BUTTON_CAPTURE,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0u16, |acc, (bit, &index)| {
+            acc | (pressed(index) as u16) << bit
+        });
+        let hat = hat_switch(
+            pressed(BUTTON_UP),
+            pressed(BUTTON_RIGHT),
+            pressed(BUTTON_DOWN),
+            pressed(BUTTON_LEFT),
+        );
+
+        let mut input_msg = vec![0x3f];
+        input_msg.extend_from_slice(&buttons.to_le_bytes());
+        input_msg.push(hat);
+        for bits in [24..36, 36..48, 48..60, 60..72] {
+            let axis = self.input_state[bits].load::<u16>() << 4;
+            input_msg.extend_from_slice(&axis.to_le_bytes());
+        }
+        input_msg.resize(64, 0);
+        input_msg
+    }
 }
 
 fn timestamp() -> u8 {
@@ -75,121 +263,261 @@ fn timestamp() -> u8 {
         & 0xFF) as u8
 }
 
-fn response(code: u8, cmd: u8, data: &[u8], hid_tx: &SyncSender<Vec<u8>>) {
+fn response(code: u8, cmd: u8, data: &[u8], outbox: &Outbox) {
     if data.len() + 2 > 64 {
         return;
     }
     let padding = vec![0; 64 - 2 - data.len()];
-    let send = [&[code, cmd], data, &padding].concat();
-    let _ = hid_tx.send(send);
+    outbox.reply([&[code, cmd], data, &padding].concat());
 }
 
-fn uart_response(code: u8, subcmd: u8, input: &[u8], data: &[u8], hid_tx: &SyncSender<Vec<u8>>) {
+fn uart_response(code: u8, subcmd: u8, input: &[u8], data: &[u8], outbox: &Outbox) {
     response(
         0x21,
         timestamp(),
-        &[&[0x81], input, &[0x0c, code, subcmd], data].concat(),
-        hid_tx,
+        &[input, &[0x0c, code, subcmd], data].concat(),
+        outbox,
     )
 }
 
-fn spi_response(addr_lo: u8, addr_hi: u8, input: &[u8], data: &[u8], hid_tx: &SyncSender<Vec<u8>>) {
+fn spi_response(addr: u32, input: &[u8], data: &[u8], outbox: &Outbox) {
     let data_len = data.len() as u8;
     uart_response(
         0x90,
         0x10,
         input,
-        &[&[addr_lo, addr_hi, 0x00, 0x00, data_len], data].concat(),
-        hid_tx,
+        &[&addr.to_le_bytes()[..], &[data_len], data].concat(),
+        outbox,
     );
 }
 
+fn spi_address(buffer: &[u8]) -> u32 {
+    u32::from_le_bytes([buffer[11], buffer[12], buffer[13], buffer[14]])
+}
+
 // All credit for this function goes to:
 // https://mzyy94.com/blog/2020/03/20/nintendo-switch-pro-controller-usb-gadget/
+//
+// Returns false if the output report isn't one the emulator understands
 fn send_response(
     buffer: &[u8],
     input: &[u8],
-    hid_tx: &SyncSender<Vec<u8>>,
-    colour: &[u8],
+    outbox: &Outbox,
+    state: &mut State,
     mac_addr: &[u8],
-) {
+) -> bool {
     if buffer.len() < 2 {
-        return;
+        return false;
     }
     if buffer[0] == 0x80 {
         match buffer[1] {
-            0x01 => response(0x81, 0x01, &[&[0, 3], mac_addr].concat(), hid_tx),
-            0x02 => response(0x81, 0x02, &[], hid_tx),
-            0x04 => { /* Input sending now (do something?) */ }
-            _ => (),
+            0x01 => response(0x81, 0x01, &[&[0, 3], mac_addr].concat(), outbox),
+            0x02 => response(0x81, 0x02, &[], outbox),
+            0x03 => response(0x81, 0x03, &[], outbox),
+            0x04 | 0x05 => (),
+            _ => return false,
         }
     } else if buffer[0] == 0x01 && buffer.len() > 16 {
         match buffer[10] {
-            0x01 => uart_response(0x81, 0x01, input, &[0x03], hid_tx),
+            0x01 => uart_response(0x81, 0x01, input, &[0x03], outbox),
             0x02 => uart_response(
                 0x82,
                 0x02,
                 input,
-                &[&[0x03, 0x48, 0x03, 0x02], mac_addr, &[0x03, 0x01]].concat(),
-                hid_tx,
+                &[
+                    &[0x03, 0x48, state.device_type.id(), 0x02],
+                    mac_addr,
+                    &[0x03, 0x01],
+                ]
+                .concat(),
+                outbox,
             ),
-            0x03 | 0x08 | 0x30 | 0x38 | 0x40 | 0x48 => {
-                uart_response(0x80, buffer[10], input, &[], hid_tx)
+            0x40 => {
+                state.imu.enabled = buffer[11] == 0x01;
+                uart_response(0x80, 0x40, input, &[], outbox)
             }
-            0x04 => uart_response(0x83, 0x04, input, &[], hid_tx),
-            0x21 => uart_response(
-                0xa0,
-                0x21,
-                input,
-                &[0x01, 0x00, 0xff, 0x00, 0x03, 0x00, 0x05, 0x01],
-                hid_tx,
-            ),
-            0x10 => match buffer[11] {
-                0x00 => {
-                    if buffer[12] == 0x60 {
-                        spi_response(0x00, 0x60, input, &magic::SERIAL_NUMBER, hid_tx)
-                    }
-                }
-                0x50 => {
-                    if buffer[12] == 0x60 {
-                        spi_response(0x50, 0x60, input, &colour, hid_tx)
-                    }
-                }
-                0x80 => {
-                    if buffer[12] == 0x60 {
-                        spi_response(0x80, 0x60, input, &magic::SENSOR_STICK_PARAMS, hid_tx)
-                    }
-                }
-                0x98 => {
-                    if buffer[12] == 0x60 {
-                        spi_response(0x98, 0x60, input, &magic::STICK_PARAMS_2, hid_tx)
-                    }
+            0x03 => match ReportMode::from_id(buffer[11]) {
+                // f_hid cuts every write to the function's report length,
+                // so a mode whose reports don't fit is refused
+                Some(mode) if mode.report_len() > state.report_length => {
+                    uart_response(0x00, 0x03, input, &[], outbox)
                 }
-                0x3d => {
-                    if buffer[12] == 0x60 {
-                        spi_response(0x3d, 0x60, input, &magic::CONFIG, hid_tx)
-                    }
+                Some(mode) => {
+                    state.report_mode = mode;
+                    uart_response(0x80, 0x03, input, &[], outbox)
                 }
-                0x10 => {
-                    if buffer[12] == 0x80 {
-                        spi_response(0x10, 0x80, input, &magic::CALIBRATION, hid_tx)
+                None => uart_response(0x80, 0x03, input, &[], outbox),
+            },
+            0x08 | 0x30 | 0x38 | 0x41 | 0x48 => uart_response(0x80, buffer[10], input, &[], outbox),
+            0x04 => uart_response(0x83, 0x04, input, &[], outbox),
+            0x21 => {
+                let data = state.mcu.configure(&buffer[11..]);
+                uart_response(0xa0, 0x21, input, &data, outbox)
+            }
+            0x22 => {
+                state.mcu.set_power(buffer[11] == 0x01);
+                uart_response(0x80, 0x22, input, &[], outbox)
+            }
+            0x10 => {
+                let addr = spi_address(buffer);
+                let data = state
+                    .spi_flash
+                    .read(addr, buffer[15].min(SPI_MAX_READ) as usize);
+                spi_response(addr, input, &data, outbox)
+            }
+            0x11 => {
+                let addr = spi_address(buffer);
+                let len = buffer[15] as usize;
+                let written = match buffer.get(16..16 + len) {
+                    Some(data) => state.spi_flash.write(addr, data),
+                    None => false,
+                };
+                uart_response(0x80, 0x11, input, &[(!written) as u8], outbox)
+            }
+            0x12 => {
+                let erased = state.spi_flash.erase_sector(spi_address(buffer));
+                uart_response(0x80, 0x12, input, &[(!erased) as u8], outbox)
+            }
+            subcmd => {
+                match state.subcommand_replies.get(&subcmd) {
+                    None | Some(SubcommandReply::Ack) => {
+                        uart_response(0x80, subcmd, input, &[], outbox)
                     }
-                }
-                0x28 => {
-                    if buffer[12] == 0x80 {
-                        spi_response(0x28, 0x80, input, &magic::SENSOR_CALIBRATION, hid_tx)
+                    Some(SubcommandReply::Data { ack, data }) => {
+                        uart_response(*ack, subcmd, input, data, outbox)
                     }
+                    Some(SubcommandReply::Ignore) => (),
                 }
-                _ => (),
-            },
+                return false;
+            }
+        }
+    } else if buffer[0] == 0x11 && buffer.len() > 11 {
+        state.mcu.request(&buffer[10..]);
+    } else if buffer[0] != 0x10 {
+        return false;
+    }
+    true
+}
+
+/// The handshake stage an output report moves the protocol to, if any
+fn handshake_stage(buffer: &[u8]) -> Option<HandshakeStage> {
+    if buffer.len() < 2 {
+        return None;
+    }
+    if buffer[0] == 0x80 {
+        match buffer[1] {
+            0x01 => Some(HandshakeStage::Status),
+            0x02 => Some(HandshakeStage::Handshake),
+            0x03 => Some(HandshakeStage::BaudRate),
+            0x04 => Some(HandshakeStage::HidOnly),
+            0x05 => Some(HandshakeStage::Suspended),
+            _ => None,
+        }
+    } else if buffer[0] == 0x01 && buffer.len() > 16 {
+        match buffer[10] {
+            0x02 => Some(HandshakeStage::DeviceInfo),
+            0x03 => Some(HandshakeStage::InputMode),
+            0x30 => Some(HandshakeStage::Active),
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+fn track_handshake(buffer: &[u8], state: &mut State, events_tx: &Sender<ControllerEvent>) {
+    let stage = match handshake_stage(buffer) {
+        // Once active, later subcommands don't take the handshake back
+        Some(_) if state.handshake == Some(HandshakeStage::Active) && buffer[0] == 0x01 => return,
+        Some(stage) if state.handshake != Some(stage) => stage,
+        _ => return,
+    };
+    state.handshake = Some(stage);
+    let _ = events_tx.send(ControllerEvent::Handshake(stage));
+    if stage == HandshakeStage::Active {
+        let _ = events_tx.send(ControllerEvent::InputActive);
+    }
+}
+
+fn send_event(buffer: &[u8], events_tx: &Sender<ControllerEvent>, last_rumble: &mut [u8; 8]) {
+    if (buffer[0] == 0x01 || buffer[0] == 0x10)
+        && buffer.len() >= 10
+        && buffer[2..10] != *last_rumble
+    {
+        last_rumble.copy_from_slice(&buffer[2..10]);
+        let _ = events_tx.send(ControllerEvent::Rumble {
+            left: rumble::decode(&buffer[2..6]),
+            right: rumble::decode(&buffer[6..10]),
+        });
+    }
+    if buffer[0] == 0x01 && buffer.len() > 16 {
+        match buffer[10] {
+            0x30 => {
+                let _ = events_tx.send(ControllerEvent::PlayerLights(lights::player_lights(
+                    buffer[11],
+                )));
+            }
+            0x38 if buffer.len() >= 36 => {
+                let _ = events_tx.send(ControllerEvent::HomeLight(lights::home_light(
+                    &buffer[11..36],
+                )));
+            }
             _ => (),
         }
     }
 }
 
-fn send_event(buffer: &[u8], hid_tx: &SyncSender<ControllerEvent>) {
-    if buffer[0] == 0x01 && buffer.len() > 16 && buffer[10] == 0x30 {
-        let _ = hid_tx.try_send(ControllerEvent::PlayerLights(buffer[11]));
+/// Passes events from the protocol thread on to the event channel until
+/// told to stop. Events are held here while the channel is full rather
+/// than dropped, except for rumble, of which only the latest is kept: the
+/// Switch can send rumble every few milliseconds, and stale rumble would
+/// otherwise crowd out the other events.
+fn forward_events(
+    events_rx: Receiver<ControllerEvent>,
+    stop_rx: Receiver<()>,
+    event_tx: SyncSender<ControllerEvent>,
+) {
+    fn hold(
+        event: ControllerEvent,
+        pending: &mut VecDeque<ControllerEvent>,
+        rumble: &mut Option<ControllerEvent>,
+    ) {
+        match event {
+            ControllerEvent::Rumble { .. } => *rumble = Some(event),
+            event => pending.push_back(event),
+        }
+    }
+
+    let mut pending = VecDeque::new();
+    let mut rumble = None;
+    loop {
+        match stop_rx.try_recv() {
+            Ok(_) | Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {}
+        }
+        match events_rx.recv_timeout(EVENT_RETRY_PERIOD) {
+            Ok(event) => hold(event, &mut pending, &mut rumble),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        for event in events_rx.try_iter() {
+            hold(event, &mut pending, &mut rumble);
+        }
+
+        while let Some(event) = pending.pop_front().or_else(|| rumble.take()) {
+            match event_tx.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(event @ ControllerEvent::Rumble { .. })) => {
+                    rumble = Some(event);
+                    break;
+                }
+                Err(TrySendError::Full(event)) => {
+                    pending.push_front(event);
+                    break;
+                }
+                Err(TrySendError::Disconnected(_)) => return,
+            }
+        }
     }
 }
 
@@ -197,11 +525,12 @@ fn send_event(buffer: &[u8], hid_tx: &SyncSender<ControllerEvent>) {
 pub struct NsProcon {
     hid_in_path: PathBuf,
     hid_out_path: PathBuf,
-    input_state: BitArr!(for 72, in Lsb0, u8),
-    colour: Vec<u8>,
+    state: Arc<Mutex<State>>,
     mac_addr: [u8; 6],
-    hid_thread_tx: Option<SyncSender<Vec<u8>>>,
+    outbox: Option<Arc<Outbox>>,
     protocol_thread_tx: Option<SyncSender<()>>,
+    report_thread_tx: Option<SyncSender<()>>,
+    event_thread_tx: Option<SyncSender<()>>,
     event_tx: SyncSender<ControllerEvent>,
     event_rx: Receiver<ControllerEvent>,
 }
@@ -212,11 +541,12 @@ impl NsProcon {
         let mut procon = NsProcon {
             hid_in_path: path.as_ref().to_path_buf(),
             hid_out_path: path.as_ref().to_path_buf(),
-            input_state: BitArray::zeroed(),
-            colour: [body_col, [0, 0, 0], body_col, body_col].concat(),
+            state: Arc::new(Mutex::new(State::create(body_col))),
             mac_addr: rand::thread_rng().gen::<[u8; 6]>(),
-            hid_thread_tx: None,
+            outbox: None,
             protocol_thread_tx: None,
+            report_thread_tx: None,
+            event_thread_tx: None,
             event_tx,
             event_rx,
         };
@@ -229,11 +559,12 @@ impl NsProcon {
         let mut procon = NsProcon {
             hid_in_path: in_path.as_ref().to_path_buf(),
             hid_out_path: out_path.as_ref().to_path_buf(),
-            input_state: BitArray::zeroed(),
-            colour: [body_col, [0, 0, 0], body_col, body_col].concat(),
+            state: Arc::new(Mutex::new(State::create(body_col))),
             mac_addr: rand::thread_rng().gen::<[u8; 6]>(),
-            hid_thread_tx: None,
+            outbox: None,
             protocol_thread_tx: None,
+            report_thread_tx: None,
+            event_thread_tx: None,
             event_tx,
             event_rx,
         };
@@ -241,16 +572,153 @@ impl NsProcon {
         procon
     }
 
+    /// Presents the controller as a different device, e.g. a single Joy-Con.
+    /// This resets the SPI flash to that device's defaults, so any custom
+    /// flash should be set afterwards.
+    pub fn with_device_type(self, device_type: DeviceType) -> NsProcon {
+        {
+            let mut state = self.state();
+            let body_col = state.spi_flash.read(0x6050, 3);
+            state.device_type = device_type;
+            state.spi_flash =
+                SpiFlash::create_for(device_type, [body_col[0], body_col[1], body_col[2]]);
+            state.input_state.set(
+                inputs::BUTTON_CHARGING_GRIP,
+                device_type == DeviceType::ProController,
+            );
+        }
+        self
+    }
+
+    /// Sets the report length of the HID function the controller writes
+    /// to. Report modes with longer reports are refused. Defaults to 64, as
+    /// in the gadget presets.
+    pub fn with_report_length(self, report_length: usize) -> NsProcon {
+        self.state().report_length = report_length;
+        self
+    }
+
+    pub fn device_type(&self) -> DeviceType {
+        self.state().device_type
+    }
+
+    /// Sets how a single Joy-Con is held. This has no effect on a Pro
+    /// Controller.
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.state().orientation = orientation;
+    }
+
+    /// Replaces the emulated SPI flash, e.g. with a dump from a real controller
+    pub fn set_spi_flash(&mut self, spi_flash: SpiFlash) {
+        self.state().spi_flash = spi_flash;
+    }
+
+    /// Returns a copy of the emulated SPI flash, including anything the
+    /// Switch has written to it
+    pub fn spi_flash(&self) -> SpiFlash {
+        self.state().spi_flash.clone()
+    }
+
+    /// Holds a single accelerometer/gyroscope reading, which is repeated in
+    /// every IMU frame until it is changed
+    pub fn set_imu(&mut self, sample: ImuSample) {
+        self.state().imu.set(sample);
+    }
+
+    /// Queues samples to be sent in order, three per input report
+    pub fn queue_imu(&mut self, samples: &[ImuSample]) {
+        self.state().imu.queue(samples);
+    }
+
+    /// Whether the Switch has enabled the IMU with subcommand 0x40
+    pub fn imu_enabled(&self) -> bool {
+        self.state().imu.enabled
+    }
+
+    /// Places an NFC tag on the emulated reader, where the Switch will find
+    /// it the next time it polls. The Switch only reads tags in NFC/IR
+    /// mode, whose 362 byte reports don't fit the 64 byte reports of the
+    /// gadget presets, so this fails unless `with_report_length` allows
+    /// for them.
+    pub fn place_tag(&mut self, tag: Ntag) -> Result<()> {
+        let mut state = self.state();
+        let needed = ReportMode::NfcIr.report_len();
+        if state.report_length < needed {
+            bail!(
+                "tags need {} byte NFC/IR reports, but the HID function's are {} bytes",
+                needed,
+                state.report_length
+            );
+        }
+        state.mcu.set_tag(Some(tag));
+        Ok(())
+    }
+
+    pub fn remove_tag(&mut self) {
+        self.state().mcu.set_tag(None);
+    }
+
+    pub fn set_battery_level(&mut self, level: BatteryLevel) {
+        self.state().battery.level = level;
+    }
+
+    /// The reported battery level, which changes over time if a discharge
+    /// curve is set
+    pub fn battery_level(&self) -> BatteryLevel {
+        self.state().battery.level()
+    }
+
+    pub fn set_charging(&mut self, charging: bool) {
+        self.state().battery.charging = charging;
+    }
+
+    pub fn set_power_source(&mut self, power_source: PowerSource) {
+        self.state().battery.power_source = power_source;
+    }
+
+    /// Simulates the battery draining (or filling while charging) by one
+    /// level every `step`. `None` stops the simulation.
+    pub fn set_discharge(&mut self, step: Option<Duration>) {
+        self.state().battery.set_discharge(step);
+    }
+
+    /// Sets how to answer a subcommand the emulator doesn't handle itself.
+    /// Unhandled subcommands are acked with no data unless configured here.
+    pub fn set_subcommand_reply(&mut self, subcmd: u8, reply: SubcommandReply) {
+        self.state().subcommand_replies.insert(subcmd, reply);
+    }
+
+    /// Sets how often input reports are sent once the Switch has finished
+    /// the handshake
+    pub fn set_report_period(&mut self, period: Duration) {
+        self.state().report_period = period;
+    }
+
+    /// Whether input reports are currently being streamed to the Switch.
+    /// In simple HID mode reports are only sent when the input is flushed.
+    pub fn is_streaming(&self) -> bool {
+        self.state().is_streaming()
+    }
+
+    /// The last handshake step the Switch performed, or `None` before it
+    /// has sent anything
+    pub fn handshake_stage(&self) -> Option<HandshakeStage> {
+        self.state().handshake
+    }
+
+    /// The input report format most recently selected by the Switch
+    pub fn report_mode(&self) -> ReportMode {
+        self.state().report_mode
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
     fn send_input(&self) -> Result<()> {
-        match &self.hid_thread_tx {
-            Some(hid_tx) => {
-                let mut input_msg = vec![0x30, timestamp(), 0x81];
-                input_msg.extend_from_slice(self.input_state.as_buffer());
-                input_msg.extend_from_slice(&[0; 52]);
-                hid_tx.try_send(input_msg)?
-            }
-            None => (),
-        };
+        if let Some(outbox) = &self.outbox {
+            outbox.report(self.state().input_report())
+        }
         Ok(())
     }
 }
@@ -259,28 +727,55 @@ impl Controller for NsProcon {
     type C = NsProcon;
 
     fn start_comms(&mut self) -> Result<()> {
-        let (hid_tx, hid_rx) = mpsc::sync_channel::<Vec<u8>>(10);
+        let outbox = Arc::new(Outbox::default());
         let (protocol_tx, protocol_rx) = mpsc::sync_channel(10);
+        let (report_tx, report_rx) = mpsc::sync_channel(1);
         let hid_read = OpenOptions::new().read(true).open(&self.hid_in_path)?;
         let hid_write = OpenOptions::new().write(true).open(&self.hid_out_path)?;
-        let colour = self.colour.clone();
-        let mac_addr = self.mac_addr.clone();
+        let state = self.state.clone();
+        let mac_addr = self.mac_addr;
         let event_tx = self.event_tx.clone();
 
         let mut buffer = [0; 64];
+        let mut last_rumble = rumble::NEUTRAL;
         let mut reader = BufReader::new(hid_read);
         let mut writer = BufWriter::new(hid_write);
 
         // Thread for writing to the HID device
+        let writer_outbox = outbox.clone();
         thread::spawn(move || {
-            for to_write in hid_rx {
+            while let Some(to_write) = writer_outbox.next() {
                 // println!("<<< {:02x?}", &to_write);
                 let _ = writer.write_all(&to_write);
                 let _ = writer.flush();
             }
         });
 
-        self.hid_thread_tx = Some(hid_tx.clone());
+        self.outbox = Some(outbox.clone());
+
+        // Thread for streaming input reports once the handshake is done
+        let report_state = self.state.clone();
+        let report_outbox = outbox.clone();
+        thread::spawn(move || loop {
+            let period = report_state.lock().unwrap().report_period;
+            match report_rx.recv_timeout(period) {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(_) | Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            let mut state = report_state.lock().unwrap();
+            if state.is_streaming() {
+                report_outbox.report(state.input_report());
+            }
+        });
+        self.report_thread_tx = Some(report_tx);
+
+        // Thread for passing on events from the protocol thread
+        let (events_tx, events_rx) = mpsc::channel::<ControllerEvent>();
+        let (event_thread_tx, event_thread_rx) = mpsc::sync_channel(1);
+        let forward_event_tx = self.event_tx.clone();
+        thread::spawn(move || forward_events(events_rx, event_thread_rx, forward_event_tx));
+        self.event_thread_tx = Some(event_thread_tx);
 
         // Thread for responding to data from the Switch
         thread::spawn(move || loop {
@@ -302,15 +797,24 @@ impl Controller for NsProcon {
             //     &buffer[0], &buffer[1], &buffer[10], &buffer[11], &buffer[12]
             // );
 
-            let input = &magic::INITIAL_INPUT;
+            let mut state = state.lock().unwrap();
+            let input = &[&[state.battery_status()], &magic::INITIAL_INPUT[..]].concat();
 
             if read >= 10 {
-                send_response(&buffer, input, &hid_tx, &colour, &mac_addr);
-                send_event(&buffer, &event_tx);
+                if !send_response(&buffer, input, &outbox, &mut state, &mac_addr) {
+                    let _ =
+                        event_tx.try_send(ControllerEvent::UnknownReport(buffer[..read].to_vec()));
+                }
+                track_handshake(&buffer, &mut state, &events_tx);
+                send_event(&buffer, &events_tx, &mut last_rumble);
             } else {
                 for i in (0..read).step_by(2) {
-                    send_response(&buffer[i..(i + 2)], input, &hid_tx, &colour, &mac_addr);
-                    send_event(&buffer[i..(i + 2)], &event_tx);
+                    let chunk = &buffer[i..(i + 2)];
+                    if !send_response(chunk, input, &outbox, &mut state, &mac_addr) {
+                        let _ = event_tx.try_send(ControllerEvent::UnknownReport(chunk.to_vec()));
+                    }
+                    track_handshake(chunk, &mut state, &events_tx);
+                    send_event(chunk, &events_tx, &mut last_rumble);
                 }
             }
         });
@@ -322,12 +826,28 @@ impl Controller for NsProcon {
             Some(protocol_thread_tx) => protocol_thread_tx.send(()),
             None => Ok(()),
         };
+        if let Some(report_thread_tx) = &self.report_thread_tx {
+            let _ = report_thread_tx.send(());
+        }
+        if let Some(event_thread_tx) = &self.event_thread_tx {
+            let _ = event_thread_tx.send(());
+        }
         self.protocol_thread_tx = None;
-        self.hid_thread_tx = None;
+        self.report_thread_tx = None;
+        self.event_thread_tx = None;
+        if let Some(outbox) = self.outbox.take() {
+            outbox.close();
+        }
     }
 
     fn set(&mut self, index: usize, value: bool, flush: bool) -> Result<()> {
-        self.input_state.set(index, value);
+        {
+            let mut state = self.state();
+            let index = state.device_type.map_button(state.orientation, index);
+            if state.device_type.has_button(index) {
+                state.input_state.set(index, value);
+            }
+        }
         if flush {
             return self.send_input();
         }
@@ -335,21 +855,29 @@ impl Controller for NsProcon {
     }
 
     fn press(&mut self, index: usize, flush: bool) -> Result<()> {
-        return self.set(index, true, flush);
+        self.set(index, true, flush)
     }
 
     fn release(&mut self, index: usize, flush: bool) -> Result<()> {
-        return self.set(index, false, flush);
+        self.set(index, false, flush)
     }
 
     fn set_axis(&mut self, index: usize, value: u16, flush: bool) -> Result<()> {
-        match index {
-            inputs::AXIS_LH => self.input_state[24..36].store(value >> 4),
-            inputs::AXIS_LV => self.input_state[36..48].store(value >> 4),
-            inputs::AXIS_RH => self.input_state[48..60].store(value >> 4),
-            inputs::AXIS_RV => self.input_state[60..72].store(value >> 4),
-            _ => (),
-        };
+        {
+            let mut state = self.state();
+            let (index, value) = state.device_type.map_axis(state.orientation, index, value);
+            if !state.device_type.has_axis(index) {
+                return Ok(());
+            }
+            let input_state = &mut state.input_state;
+            match index {
+                inputs::AXIS_LH => input_state[24..36].store(value >> 4),
+                inputs::AXIS_LV => input_state[36..48].store(value >> 4),
+                inputs::AXIS_RH => input_state[48..60].store(value >> 4),
+                inputs::AXIS_RV => input_state[60..72].store(value >> 4),
+                _ => (),
+            };
+        }
         if flush {
             return self.send_input();
         }
@@ -357,14 +885,180 @@ impl Controller for NsProcon {
     }
 
     fn flush_input(&mut self) -> Result<()> {
-        return self.send_input();
+        self.send_input()
     }
 
     fn listen_for_events(&mut self) -> &Receiver<ControllerEvent> {
-        return &self.event_rx;
+        &self.event_rx
     }
 
     fn log_state(&self) {
-        log::debug!("{:?}", self.input_state);
+        log::debug!("{:?}", self.state().input_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{Rumble, RumbleBand};
+    use crate::usb_gadget::ns_procon::ns_procons;
+
+    /// Sends a subcommand and returns the reply, from the ack byte on
+    fn subcommand(state: &mut State, subcmd: u8, args: &[u8]) -> Vec<u8> {
+        let outbox = Outbox::default();
+        let mut buffer = [0; 64];
+        buffer[0] = 0x01;
+        buffer[10] = subcmd;
+        buffer[11..11 + args.len()].copy_from_slice(args);
+        send_response(&buffer, &[0; 10], &outbox, state, &[0; 6]);
+        outbox.next().unwrap()[13..].to_vec()
+    }
+
+    /// Sends subcommand 0x03 and returns the ack byte of the reply
+    fn select_report_mode(state: &mut State, id: u8) -> u8 {
+        subcommand(state, 0x03, &[id])[0]
+    }
+
+    #[test]
+    fn spi_reads_are_clamped() {
+        let mut state = State::create([0x12, 0x34, 0x56]);
+        let reply = subcommand(&mut state, 0x10, &[0x50, 0x60, 0x00, 0x00, 0x03]);
+        assert_eq!(
+            reply[..9],
+            [0x90, 0x10, 0x50, 0x60, 0x00, 0x00, 0x03, 0x12, 0x34]
+        );
+
+        let reply = subcommand(&mut state, 0x10, &[0x00, 0x60, 0x00, 0x00, 0xff]);
+        assert_eq!(reply[6], SPI_MAX_READ);
+        assert_eq!(
+            reply[7..7 + SPI_MAX_READ as usize],
+            state.spi_flash.read(0x6000, 0x1d)[..]
+        );
+    }
+
+    #[test]
+    fn spi_writes() {
+        let mut state = State::create([0; 3]);
+        let reply = subcommand(
+            &mut state,
+            0x11,
+            &[0x00, 0x20, 0x00, 0x00, 0x02, 0xab, 0xcd],
+        );
+        assert_eq!(reply[..3], [0x80, 0x11, 0x00]);
+        assert_eq!(state.spi_flash.read(0x2000, 2), [0xab, 0xcd]);
+
+        // Off the end of the chip
+        let reply = subcommand(
+            &mut state,
+            0x11,
+            &[0xff, 0xff, 0x07, 0x00, 0x02, 0xab, 0xcd],
+        );
+        assert_eq!(reply[..3], [0x80, 0x11, 0x01]);
+
+        let reply = subcommand(&mut state, 0x12, &[0x00, 0x20, 0x00, 0x00]);
+        assert_eq!(reply[..3], [0x80, 0x12, 0x00]);
+        assert_eq!(state.spi_flash.read(0x2000, 2), [0xff, 0xff]);
+    }
+
+    #[test]
+    fn nfc_ir_reports_dont_fit_preset() {
+        let report_length = ns_procons().hid_functions()[0].report_length as usize;
+        assert_eq!(report_length, DEFAULT_REPORT_LENGTH);
+
+        let mut state = State::create([0; 3]);
+        state.report_mode = ReportMode::NfcIr;
+        let nfc_ir_len = state.input_report().len();
+        assert_eq!(nfc_ir_len, ReportMode::NfcIr.report_len());
+        assert!(nfc_ir_len > report_length);
+
+        let mut state = State::create([0; 3]);
+        assert_eq!(select_report_mode(&mut state, 0x31), 0x00);
+        assert_eq!(state.report_mode, ReportMode::Full);
+        assert!(state.input_report().len() <= report_length);
+    }
+
+    #[test]
+    fn tags_need_nfc_ir_reports() {
+        let tag = Ntag::from_bytes(vec![0; ntag::SIZE]).unwrap();
+        let mut procon = NsProcon::create("/dev/null", [0; 3]);
+        assert!(procon.place_tag(tag.clone()).is_err());
+
+        let mut procon = procon.with_report_length(ReportMode::NfcIr.report_len());
+        assert!(procon.place_tag(tag).is_ok());
+    }
+
+    #[test]
+    fn nfc_ir_allowed_on_long_function() {
+        let mut state = State::create([0; 3]);
+        state.report_length = ReportMode::NfcIr.report_len();
+        assert_eq!(select_report_mode(&mut state, 0x31), 0x80);
+        assert_eq!(state.report_mode, ReportMode::NfcIr);
+        assert_eq!(state.input_report().len(), state.report_length);
+    }
+
+    fn rumble(amplitude: f32) -> ControllerEvent {
+        let band = RumbleBand {
+            frequency: 160.0,
+            amplitude,
+        };
+        let rumble = Rumble {
+            high: band,
+            low: band,
+        };
+        ControllerEvent::Rumble {
+            left: rumble,
+            right: rumble,
+        }
+    }
+
+    #[test]
+    fn forwarding_keeps_only_the_latest_rumble() {
+        let (events_tx, events_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::sync_channel(1);
+        let (event_tx, event_rx) = mpsc::sync_channel(1);
+        // Fill the event channel so everything has to wait in the forwarder
+        event_tx.send(ControllerEvent::InputActive).unwrap();
+        let forwarder = thread::spawn(move || forward_events(events_rx, stop_rx, event_tx));
+
+        for i in 1..=5 {
+            events_tx.send(rumble(i as f32 / 10.0)).unwrap();
+        }
+        events_tx
+            .send(ControllerEvent::Handshake(HandshakeStage::Status))
+            .unwrap();
+        thread::sleep(EVENT_RETRY_PERIOD * 3);
+
+        let received = |timeout| event_rx.recv_timeout(timeout).unwrap();
+        assert!(matches!(
+            received(EVENT_RETRY_PERIOD),
+            ControllerEvent::InputActive
+        ));
+        assert!(matches!(
+            received(EVENT_RETRY_PERIOD * 10),
+            ControllerEvent::Handshake(HandshakeStage::Status)
+        ));
+        match received(EVENT_RETRY_PERIOD * 10) {
+            ControllerEvent::Rumble { left, .. } => assert_eq!(left.high.amplitude, 0.5),
+            _ => panic!("expected rumble"),
+        }
+        thread::sleep(EVENT_RETRY_PERIOD * 3);
+        assert!(event_rx.try_recv().is_err());
+
+        stop_tx.send(()).unwrap();
+        forwarder.join().unwrap();
+    }
+
+    #[test]
+    fn stopping_ends_forwarding_without_a_listener() {
+        let (events_tx, events_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::sync_channel(1);
+        let (event_tx, _event_rx) = mpsc::sync_channel(1);
+        let forwarder = thread::spawn(move || forward_events(events_rx, stop_rx, event_tx));
+
+        for stage in [HandshakeStage::Status, HandshakeStage::Handshake].iter() {
+            events_tx.send(ControllerEvent::Handshake(*stage)).unwrap();
+        }
+        stop_tx.send(()).unwrap();
+        forwarder.join().unwrap();
     }
 }
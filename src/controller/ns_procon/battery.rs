@@ -0,0 +1,204 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatteryLevel {
+    Empty,
+    Critical,
+    Low,
+    Medium,
+    Full,
+}
+
+impl BatteryLevel {
+    fn nibble(self) -> u8 {
+        match self {
+            BatteryLevel::Empty => 0x0,
+            BatteryLevel::Critical => 0x2,
+            BatteryLevel::Low => 0x4,
+            BatteryLevel::Medium => 0x6,
+            BatteryLevel::Full => 0x8,
+        }
+    }
+
+    fn lower(self) -> BatteryLevel {
+        match self {
+            BatteryLevel::Full => BatteryLevel::Medium,
+            BatteryLevel::Medium => BatteryLevel::Low,
+            BatteryLevel::Low => BatteryLevel::Critical,
+            BatteryLevel::Critical | BatteryLevel::Empty => BatteryLevel::Empty,
+        }
+    }
+
+    fn higher(self) -> BatteryLevel {
+        match self {
+            BatteryLevel::Empty => BatteryLevel::Critical,
+            BatteryLevel::Critical => BatteryLevel::Low,
+            BatteryLevel::Low => BatteryLevel::Medium,
+            BatteryLevel::Medium | BatteryLevel::Full => BatteryLevel::Full,
+        }
+    }
+}
+
+/// Number of battery levels
+const LEVELS: u128 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerSource {
+    Battery,
+    /// Powered by the Switch or over USB
+    Usb,
+}
+
+#[derive(Debug)]
+struct Discharge {
+    step: Duration,
+    last_step: Instant,
+}
+
+#[derive(Debug)]
+pub(super) struct Battery {
+    pub(super) level: BatteryLevel,
+    pub(super) charging: bool,
+    pub(super) power_source: PowerSource,
+    discharge: Option<Discharge>,
+}
+
+impl Default for Battery {
+    fn default() -> Self {
+        Battery {
+            level: BatteryLevel::Full,
+            charging: false,
+            power_source: PowerSource::Usb,
+            discharge: None,
+        }
+    }
+}
+
+impl Battery {
+    /// Moves the level one step every `step`: down while discharging, up
+    /// while charging. `None` holds the level where it is.
+    pub(super) fn set_discharge(&mut self, step: Option<Duration>) {
+        self.discharge = step.map(|step| Discharge {
+            step,
+            last_step: Instant::now(),
+        });
+    }
+
+    fn apply_discharge(&mut self) {
+        let discharge = match &mut self.discharge {
+            Some(discharge) if !discharge.step.is_zero() => discharge,
+            _ => return,
+        };
+        let step = discharge.step.as_nanos();
+        let steps = discharge.last_step.elapsed().as_nanos() / step;
+        if steps == 0 {
+            return;
+        }
+        // Any more steps than there are levels end up at the same level
+        for _ in 0..steps.min(LEVELS) {
+            self.level = if self.charging {
+                self.level.higher()
+            } else {
+                self.level.lower()
+            };
+        }
+        discharge.last_step += Duration::from_nanos((steps * step) as u64);
+    }
+
+    pub(super) fn level(&mut self) -> BatteryLevel {
+        self.apply_discharge();
+        self.level
+    }
+
+    /// Encodes the battery and connection byte sent in every input report
+    pub(super) fn status(&mut self) -> u8 {
+        self.apply_discharge();
+        let charging = if self.charging { 0x1 } else { 0x0 };
+        let powered = match self.power_source {
+            PowerSource::Battery => 0x0,
+            PowerSource::Usb => 0x1,
+        };
+        (self.level.nibble() | charging) << 4 | powered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A battery that last stepped `ago`, stepping every `step`
+    fn discharging(level: BatteryLevel, step: Duration, ago: Duration) -> Battery {
+        let mut battery = Battery {
+            level,
+            ..Default::default()
+        };
+        battery.set_discharge(Some(step));
+        battery.discharge.as_mut().unwrap().last_step -= ago;
+        battery
+    }
+
+    #[test]
+    fn status_encoding() {
+        let mut battery = Battery::default();
+        assert_eq!(battery.status(), 0x81);
+
+        battery.level = BatteryLevel::Low;
+        battery.charging = true;
+        battery.power_source = PowerSource::Battery;
+        assert_eq!(battery.status(), 0x50);
+
+        battery.level = BatteryLevel::Empty;
+        battery.charging = false;
+        assert_eq!(battery.status(), 0x00);
+        battery.level = BatteryLevel::Critical;
+        assert_eq!(battery.status(), 0x20);
+        battery.level = BatteryLevel::Medium;
+        assert_eq!(battery.status(), 0x60);
+    }
+
+    #[test]
+    fn holds_level_without_discharge() {
+        let mut battery = Battery::default();
+        assert_eq!(battery.level(), BatteryLevel::Full);
+
+        let mut battery = discharging(BatteryLevel::Full, Duration::ZERO, Duration::from_secs(1));
+        assert_eq!(battery.level(), BatteryLevel::Full);
+    }
+
+    #[test]
+    fn discharges_one_level_per_step() {
+        let step = Duration::from_millis(100);
+        let mut battery = discharging(BatteryLevel::Full, step, Duration::from_millis(250));
+        assert_eq!(battery.level(), BatteryLevel::Low);
+        // The part of a step that has passed still counts towards the next
+        let since_step = battery.discharge.as_ref().unwrap().last_step.elapsed();
+        assert!(since_step >= Duration::from_millis(50) && since_step < step);
+        assert_eq!(battery.level(), BatteryLevel::Low);
+    }
+
+    #[test]
+    fn charges_one_level_per_step() {
+        let step = Duration::from_millis(100);
+        let mut battery = discharging(BatteryLevel::Empty, step, Duration::from_millis(150));
+        battery.charging = true;
+        assert_eq!(battery.level(), BatteryLevel::Critical);
+    }
+
+    #[test]
+    fn stops_at_the_last_level() {
+        // A million steps have passed, which are applied at once
+        let mut battery = discharging(
+            BatteryLevel::Full,
+            Duration::from_micros(1),
+            Duration::from_secs(1),
+        );
+        assert_eq!(battery.level(), BatteryLevel::Empty);
+        assert!(
+            battery.discharge.as_ref().unwrap().last_step.elapsed() < Duration::from_millis(100)
+        );
+
+        battery.charging = true;
+        battery.discharge.as_mut().unwrap().last_step -= Duration::from_secs(1);
+        assert_eq!(battery.level(), BatteryLevel::Full);
+    }
+}
@@ -0,0 +1,208 @@
+use super::inputs::*;
+use super::nso::{genesis, n64, nes, snes};
+
+/// Which controller the emulator presents itself as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceType {
+    ProController,
+    JoyConL,
+    JoyConR,
+    /// Nintendo Switch Online NES controller, left of the pair
+    NesL,
+    /// Nintendo Switch Online NES controller, right of the pair
+    NesR,
+    Snes,
+    N64,
+    Genesis,
+}
+
+/// How a single Joy-Con is held. Sideways Joy-Cons are driven with the
+/// usual face button, shoulder and left/right stick indices, which are
+/// rotated onto the Joy-Con's own buttons and stick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Vertical,
+    Sideways,
+}
+
+const JOYCON_L_BUTTONS: [usize; 11] = [
+    BUTTON_DOWN,
+    BUTTON_UP,
+    BUTTON_RIGHT,
+    BUTTON_LEFT,
+    BUTTON_LSR,
+    BUTTON_LSL,
+    BUTTON_L,
+    BUTTON_ZL,
+    BUTTON_MINUS,
+    BUTTON_L_STICK,
+    BUTTON_CAPTURE,
+];
+
+const JOYCON_R_BUTTONS: [usize; 11] = [
+    BUTTON_Y,
+    BUTTON_X,
+    BUTTON_B,
+    BUTTON_A,
+    BUTTON_RSR,
+    BUTTON_RSL,
+    BUTTON_R,
+    BUTTON_ZR,
+    BUTTON_PLUS,
+    BUTTON_R_STICK,
+    BUTTON_HOME,
+];
+
+const NES_BUTTONS: [usize; 10] = [
+    nes::BUTTON_A,
+    nes::BUTTON_B,
+    nes::BUTTON_SELECT,
+    nes::BUTTON_START,
+    nes::BUTTON_L,
+    nes::BUTTON_R,
+    nes::BUTTON_DOWN,
+    nes::BUTTON_UP,
+    nes::BUTTON_RIGHT,
+    nes::BUTTON_LEFT,
+];
+
+const SNES_BUTTONS: [usize; 14] = [
+    snes::BUTTON_A,
+    snes::BUTTON_B,
+    snes::BUTTON_X,
+    snes::BUTTON_Y,
+    snes::BUTTON_L,
+    snes::BUTTON_R,
+    snes::BUTTON_ZL,
+    snes::BUTTON_ZR,
+    snes::BUTTON_SELECT,
+    snes::BUTTON_START,
+    snes::BUTTON_DOWN,
+    snes::BUTTON_UP,
+    snes::BUTTON_RIGHT,
+    snes::BUTTON_LEFT,
+];
+
+const N64_BUTTONS: [usize; 17] = [
+    n64::BUTTON_A,
+    n64::BUTTON_B,
+    n64::BUTTON_C_UP,
+    n64::BUTTON_C_DOWN,
+    n64::BUTTON_C_LEFT,
+    n64::BUTTON_C_RIGHT,
+    n64::BUTTON_L,
+    n64::BUTTON_R,
+    n64::BUTTON_Z,
+    n64::BUTTON_ZR,
+    n64::BUTTON_START,
+    n64::BUTTON_HOME,
+    n64::BUTTON_CAPTURE,
+    n64::BUTTON_DOWN,
+    n64::BUTTON_UP,
+    n64::BUTTON_RIGHT,
+    n64::BUTTON_LEFT,
+];
+
+const GENESIS_BUTTONS: [usize; 14] = [
+    genesis::BUTTON_A,
+    genesis::BUTTON_B,
+    genesis::BUTTON_C,
+    genesis::BUTTON_X,
+    genesis::BUTTON_Y,
+    genesis::BUTTON_Z,
+    genesis::BUTTON_MODE,
+    genesis::BUTTON_START,
+    genesis::BUTTON_HOME,
+    genesis::BUTTON_CAPTURE,
+    genesis::BUTTON_DOWN,
+    genesis::BUTTON_UP,
+    genesis::BUTTON_RIGHT,
+    genesis::BUTTON_LEFT,
+];
+
+impl DeviceType {
+    /// Controller type byte used in the device info reply and SPI flash
+    pub(super) fn id(self) -> u8 {
+        match self {
+            DeviceType::JoyConL => 0x01,
+            DeviceType::JoyConR => 0x02,
+            DeviceType::ProController => 0x03,
+            DeviceType::NesL => 0x09,
+            DeviceType::NesR => 0x0a,
+            DeviceType::Snes => 0x0b,
+            DeviceType::N64 => 0x0c,
+            DeviceType::Genesis => 0x0d,
+        }
+    }
+
+    /// Connection info bits of the battery byte
+    pub(super) fn connection_info(self) -> u8 {
+        match self {
+            DeviceType::JoyConL | DeviceType::JoyConR | DeviceType::NesL | DeviceType::NesR => 0x6,
+            _ => 0x0,
+        }
+    }
+
+    pub(super) fn has_button(self, index: usize) -> bool {
+        match self {
+            DeviceType::ProController => true,
+            DeviceType::JoyConL => JOYCON_L_BUTTONS.contains(&index),
+            DeviceType::JoyConR => JOYCON_R_BUTTONS.contains(&index),
+            DeviceType::NesL | DeviceType::NesR => NES_BUTTONS.contains(&index),
+            DeviceType::Snes => SNES_BUTTONS.contains(&index),
+            DeviceType::N64 => N64_BUTTONS.contains(&index),
+            DeviceType::Genesis => GENESIS_BUTTONS.contains(&index),
+        }
+    }
+
+    pub(super) fn has_axis(self, index: usize) -> bool {
+        match self {
+            DeviceType::ProController => true,
+            DeviceType::JoyConL | DeviceType::N64 => index == AXIS_LH || index == AXIS_LV,
+            DeviceType::JoyConR => index == AXIS_RH || index == AXIS_RV,
+            DeviceType::NesL | DeviceType::NesR | DeviceType::Snes | DeviceType::Genesis => false,
+        }
+    }
+
+    /// Maps a button as seen by the player onto the device's own button
+    pub(super) fn map_button(self, orientation: Orientation, index: usize) -> usize {
+        if orientation == Orientation::Vertical {
+            return index;
+        }
+        match (self, index) {
+            (DeviceType::JoyConL, BUTTON_A) => BUTTON_DOWN,
+            (DeviceType::JoyConL, BUTTON_B) => BUTTON_LEFT,
+            (DeviceType::JoyConL, BUTTON_X) => BUTTON_RIGHT,
+            (DeviceType::JoyConL, BUTTON_Y) => BUTTON_UP,
+            (DeviceType::JoyConL, BUTTON_L) => BUTTON_LSL,
+            (DeviceType::JoyConL, BUTTON_R) => BUTTON_LSR,
+            (DeviceType::JoyConR, BUTTON_A) => BUTTON_X,
+            (DeviceType::JoyConR, BUTTON_B) => BUTTON_A,
+            (DeviceType::JoyConR, BUTTON_X) => BUTTON_Y,
+            (DeviceType::JoyConR, BUTTON_Y) => BUTTON_B,
+            (DeviceType::JoyConR, BUTTON_L) => BUTTON_RSL,
+            (DeviceType::JoyConR, BUTTON_R) => BUTTON_RSR,
+            _ => index,
+        }
+    }
+
+    /// Maps a stick axis as seen by the player onto the device's own stick,
+    /// rotating it a quarter turn for sideways Joy-Cons
+    pub(super) fn map_axis(
+        self,
+        orientation: Orientation,
+        index: usize,
+        value: u16,
+    ) -> (usize, u16) {
+        if orientation == Orientation::Vertical {
+            return (index, value);
+        }
+        match (self, index) {
+            (DeviceType::JoyConL, AXIS_LH) => (AXIS_LV, !value),
+            (DeviceType::JoyConL, AXIS_LV) => (AXIS_LH, value),
+            (DeviceType::JoyConR, AXIS_RH) => (AXIS_RV, value),
+            (DeviceType::JoyConR, AXIS_RV) => (AXIS_RH, !value),
+            _ => (index, value),
+        }
+    }
+}
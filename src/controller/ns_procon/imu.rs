@@ -0,0 +1,57 @@
+use std::collections::VecDeque;
+
+/// Number of IMU frames packed into every full-mode input report
+pub const FRAMES_PER_REPORT: usize = 3;
+
+/// A single six-axis sample, in the raw sensor units the Switch expects
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    pub accel: [i16; 3],
+    pub gyro: [i16; 3],
+}
+
+impl ImuSample {
+    fn encode(&self) -> [u8; 12] {
+        let mut frame = [0; 12];
+        for (i, value) in self.accel.iter().chain(self.gyro.iter()).enumerate() {
+            frame[i * 2..i * 2 + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        frame
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Imu {
+    pub(super) enabled: bool,
+    current: ImuSample,
+    queue: VecDeque<ImuSample>,
+}
+
+impl Imu {
+    /// Replaces the held sample and drops anything still queued
+    pub(super) fn set(&mut self, sample: ImuSample) {
+        self.queue.clear();
+        self.current = sample;
+    }
+
+    pub(super) fn queue(&mut self, samples: &[ImuSample]) {
+        self.queue.extend(samples);
+    }
+
+    /// Encodes the next three frames, oldest first. Queued samples are
+    /// consumed in order, and once the queue runs dry the last sample is
+    /// repeated. Reports carry zeros until the host enables the IMU.
+    pub(super) fn frames(&mut self) -> [u8; 12 * FRAMES_PER_REPORT] {
+        let mut frames = [0; 12 * FRAMES_PER_REPORT];
+        if !self.enabled {
+            return frames;
+        }
+        for frame in frames.chunks_mut(12) {
+            if let Some(sample) = self.queue.pop_front() {
+                self.current = sample;
+            }
+            frame.copy_from_slice(&self.current.encode());
+        }
+        frames
+    }
+}
@@ -0,0 +1,161 @@
+use crate::controller::{HomeLight, HomeLightCycle, LedState, PlayerLights};
+use std::time::Duration;
+
+/// LED patterns the Switch uses for players 1 to 8
+const PLAYER_PATTERNS: [u8; 8] = [0x1, 0x3, 0x7, 0xf, 0x9, 0x5, 0xd, 0x6];
+
+/// Decodes the argument of subcommand 0x30: the low nibble turns LEDs on,
+/// the high nibble makes them flash
+pub(super) fn player_lights(arg: u8) -> PlayerLights {
+    let on = arg & 0x0f;
+    let flashing = arg >> 4;
+
+    let mut leds = [LedState::Off; 4];
+    for (i, led) in leds.iter_mut().enumerate() {
+        if flashing & (1 << i) != 0 {
+            *led = LedState::Flashing;
+        } else if on & (1 << i) != 0 {
+            *led = LedState::On;
+        }
+    }
+
+    let pattern = on | flashing;
+    PlayerLights {
+        leds,
+        player: PLAYER_PATTERNS
+            .iter()
+            .position(|&p| p == pattern)
+            .map(|i| i as u8 + 1),
+    }
+}
+
+/// Decodes the 25 bytes of subcommand 0x38, following
+/// https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/bluetooth_hid_subcommands_notes.md
+pub(super) fn home_light(data: &[u8]) -> HomeLight {
+    let count = (data[0] >> 4) as usize;
+    let base = (data[0] & 0x0f) as u64;
+
+    let cycles = (0..count)
+        .map(|i| {
+            let group = 2 + (i / 2) * 3;
+            let intensity = if i % 2 == 0 {
+                data[group] >> 4
+            } else {
+                data[group] & 0x0f
+            };
+            let timing = data[group + 1 + i % 2];
+            HomeLightCycle {
+                intensity,
+                fade: timing >> 4,
+                duration: timing & 0x0f,
+            }
+        })
+        .collect();
+
+    HomeLight {
+        // 1 to 15 spans 8 ms to 175 ms
+        base_duration: match base {
+            0 => Duration::ZERO,
+            _ => Duration::from_micros(8_000 + (base - 1) * 167_000 / 14),
+        },
+        start_intensity: data[1] >> 4,
+        repeat: data[1] & 0x0f,
+        cycles,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LedState::*;
+
+    #[test]
+    fn player_patterns() {
+        for (i, &pattern) in PLAYER_PATTERNS.iter().enumerate() {
+            assert_eq!(player_lights(pattern).player, Some(i as u8 + 1));
+        }
+        assert_eq!(
+            player_lights(0x0d),
+            PlayerLights {
+                leds: [On, Off, On, On],
+                player: Some(7),
+            }
+        );
+    }
+
+    #[test]
+    fn flashing_patterns() {
+        assert_eq!(
+            player_lights(0xf0),
+            PlayerLights {
+                leds: [Flashing; 4],
+                player: Some(4),
+            }
+        );
+        // Flashing LEDs count towards the pattern
+        assert_eq!(
+            player_lights(0x21),
+            PlayerLights {
+                leds: [On, Flashing, Off, Off],
+                player: Some(2),
+            }
+        );
+        // A flashing bit wins over an on bit for the same LED
+        assert_eq!(player_lights(0x11).leds, [Flashing, Off, Off, Off]);
+    }
+
+    #[test]
+    fn non_standard_patterns() {
+        assert_eq!(
+            player_lights(0x00),
+            PlayerLights {
+                leds: [Off; 4],
+                player: None,
+            }
+        );
+        assert_eq!(player_lights(0x02).player, None);
+        assert_eq!(player_lights(0x0a).player, None);
+    }
+
+    #[test]
+    fn home_light_cycles() {
+        let mut data = [0; 25];
+        data[..7].copy_from_slice(&[0x3f, 0xa3, 0xf0, 0x12, 0x34, 0x80, 0x56]);
+        assert_eq!(
+            home_light(&data),
+            HomeLight {
+                base_duration: Duration::from_millis(175),
+                start_intensity: 0xa,
+                repeat: 3,
+                cycles: vec![
+                    HomeLightCycle {
+                        intensity: 0xf,
+                        fade: 1,
+                        duration: 2,
+                    },
+                    HomeLightCycle {
+                        intensity: 0x0,
+                        fade: 3,
+                        duration: 4,
+                    },
+                    HomeLightCycle {
+                        intensity: 0x8,
+                        fade: 5,
+                        duration: 6,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn home_light_base_duration() {
+        let mut data = [0; 25];
+        data[0] = 0x01;
+        assert_eq!(home_light(&data).base_duration, Duration::from_millis(8));
+        data[0] = 0x00;
+        let off = home_light(&data);
+        assert_eq!(off.base_duration, Duration::ZERO);
+        assert!(off.cycles.is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+use super::ntag::Ntag;
+use std::collections::VecDeque;
+
+/// Size of the NFC/IR MCU payload carried at the end of a 0x31 report
+pub(super) const REPORT_SIZE: usize = 313;
+
+/// Bytes of tag data that fit in the first read response packet
+const FIRST_READ_CHUNK: usize = 245;
+
+mod magic {
+    pub const READ_HEADER: [u8; 15] = [
+        0x00, 0x00, 0x00, 0x7d, 0xfd, 0xf0, 0x79, 0x36, 0x51, 0xab, 0xd7, 0x66, 0x32, 0x0b, 0x24,
+    ];
+}
+
+/// CRC-8 (polynomial 0x07) used to check MCU payloads
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Builds an MCU payload from a report body, padding it out and appending
+/// the checksum
+fn report(data: &[u8]) -> [u8; REPORT_SIZE] {
+    let mut report = [0; REPORT_SIZE];
+    report[..data.len()].copy_from_slice(data);
+    report[REPORT_SIZE - 1] = crc8(&report[..REPORT_SIZE - 1]);
+    report
+}
+
+/// Payload sent while the MCU has nothing to report
+fn idle_report() -> [u8; REPORT_SIZE] {
+    report(&[0xff])
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Mode {
+    #[default]
+    Off,
+    Standby,
+    Nfc,
+}
+
+impl Mode {
+    fn id(self) -> u8 {
+        match self {
+            Mode::Off | Mode::Standby => 0x01,
+            Mode::Nfc => 0x04,
+        }
+    }
+}
+
+/// Emulation of the NFC/IR MCU, driven by subcommands 0x21/0x22 and
+/// output report 0x11, and answering through the payload of 0x31 reports
+#[derive(Debug, Default)]
+pub(super) struct Mcu {
+    mode: Mode,
+    polling: bool,
+    tag: Option<Ntag>,
+    pending: VecDeque<[u8; REPORT_SIZE]>,
+}
+
+impl Mcu {
+    pub(super) fn set_tag(&mut self, tag: Option<Ntag>) {
+        self.tag = tag;
+    }
+
+    /// Subcommand 0x22: resume or suspend the MCU
+    pub(super) fn set_power(&mut self, on: bool) {
+        self.mode = if on { Mode::Standby } else { Mode::Off };
+        self.polling = false;
+        self.pending.clear();
+    }
+
+    /// Subcommand 0x21: applies an MCU configuration and returns the reply
+    /// data
+    pub(super) fn configure(&mut self, config: &[u8]) -> Vec<u8> {
+        if self.mode != Mode::Off && config[0] == 0x21 && config[1] == 0x00 {
+            match config[2] {
+                0x01 => self.mode = Mode::Standby,
+                0x04 => self.mode = Mode::Nfc,
+                _ => (),
+            }
+        }
+        self.status().to_vec()
+    }
+
+    /// Output report 0x11: a request for MCU data, starting at the MCU
+    /// command byte
+    pub(super) fn request(&mut self, request: &[u8]) {
+        match request[0] {
+            0x01 => self.pending.push_back(report(&self.status())),
+            0x02 if self.mode == Mode::Nfc => match request[1] {
+                0x01 => self.polling = true,
+                0x02 => self.polling = false,
+                0x06 => self.queue_read(),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    /// Returns the payload for the next 0x31 report
+    pub(super) fn report(&mut self) -> [u8; REPORT_SIZE] {
+        if let Some(pending) = self.pending.pop_front() {
+            return pending;
+        }
+        match self.mode {
+            Mode::Nfc => self.nfc_state(),
+            Mode::Off | Mode::Standby => idle_report(),
+        }
+    }
+
+    fn status(&self) -> [u8; 8] {
+        [0x01, 0x00, 0xff, 0x00, 0x03, 0x00, 0x05, self.mode.id()]
+    }
+
+    fn nfc_state(&self) -> [u8; REPORT_SIZE] {
+        let header = [0x2a, 0x00, 0x05, 0x00, 0x00, 0x09, 0x31];
+        match (&self.tag, self.polling) {
+            (Some(tag), true) => report(
+                &[
+                    &header[..],
+                    &[0x09, 0x00, 0x00, 0x00, 0x01, 0x01, 0x02, 0x00, 0x07],
+                    &tag.uid(),
+                ]
+                .concat(),
+            ),
+            (None, true) => report(&[&header[..], &[0x01]].concat()),
+            (_, false) => report(&[&header[..], &[0x00]].concat()),
+        }
+    }
+
+    /// Queues the two packets a real MCU sends in response to an NTAG read
+    fn queue_read(&mut self) {
+        let tag = match &self.tag {
+            Some(tag) if self.polling => tag,
+            _ => return,
+        };
+        let (first, second) = tag.as_bytes().split_at(FIRST_READ_CHUNK);
+
+        let mut packet = [
+            &[
+                0x3a, 0x00, 0x07, 0x01, 0x00, 0x01, 0x31, 0x02, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00,
+                0x07,
+            ][..],
+            &tag.uid(),
+            &magic::READ_HEADER,
+        ]
+        .concat();
+        packet.resize(67, 0);
+        packet.extend_from_slice(first);
+        self.pending.push_back(report(&packet));
+
+        let packet = [&[0x3a, 0x00, 0x07, 0x02, 0x00, 0x09, 0x27][..], second].concat();
+        self.pending.push_back(report(&packet));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::ns_procon::ntag;
+
+    fn tag() -> Ntag {
+        let data = (0..ntag::SIZE).map(|i| i as u8).collect();
+        Ntag::from_bytes(data).unwrap()
+    }
+
+    fn assert_checksum(report: &[u8; REPORT_SIZE]) {
+        assert_eq!(report[REPORT_SIZE - 1], crc8(&report[..REPORT_SIZE - 1]));
+    }
+
+    /// An MCU in NFC mode, polling for tags
+    fn polling_mcu(tag: Option<Ntag>) -> Mcu {
+        let mut mcu = Mcu::default();
+        mcu.set_tag(tag);
+        mcu.set_power(true);
+        mcu.configure(&[0x21, 0x00, 0x04]);
+        mcu.request(&[0x02, 0x01]);
+        mcu
+    }
+
+    #[test]
+    fn crc8_check_value() {
+        assert_eq!(crc8(b"123456789"), 0xf4);
+    }
+
+    #[test]
+    fn idle_until_configured() {
+        let mut mcu = Mcu::default();
+        // Configuring is ignored while the MCU is off
+        assert_eq!(mcu.configure(&[0x21, 0x00, 0x04])[7], 0x01);
+        assert_eq!(mcu.report(), idle_report());
+
+        mcu.set_power(true);
+        assert_eq!(
+            mcu.configure(&[0x21, 0x00, 0x04]),
+            [0x01, 0x00, 0xff, 0x00, 0x03, 0x00, 0x05, 0x04]
+        );
+    }
+
+    #[test]
+    fn status_request() {
+        let mut mcu = Mcu::default();
+        mcu.set_power(true);
+        mcu.request(&[0x01]);
+        let status = mcu.report();
+        assert_eq!(
+            status[..8],
+            [0x01, 0x00, 0xff, 0x00, 0x03, 0x00, 0x05, 0x01]
+        );
+        assert_checksum(&status);
+        // The status is only sent once
+        assert_eq!(mcu.report(), idle_report());
+    }
+
+    #[test]
+    fn polls_without_tag() {
+        let mut mcu = Mcu::default();
+        mcu.set_power(true);
+        // NFC commands are ignored until NFC mode is configured
+        mcu.request(&[0x02, 0x01]);
+        assert_eq!(mcu.report(), idle_report());
+
+        let mut mcu = polling_mcu(None);
+        let report = mcu.report();
+        assert_eq!(
+            report[..8],
+            [0x2a, 0x00, 0x05, 0x00, 0x00, 0x09, 0x31, 0x01]
+        );
+        assert_checksum(&report);
+
+        mcu.request(&[0x02, 0x02]);
+        assert_eq!(mcu.report()[7], 0x00);
+    }
+
+    #[test]
+    fn polls_with_tag() {
+        let mut mcu = polling_mcu(Some(tag()));
+        let report = mcu.report();
+        assert_eq!(report[7], 0x09);
+        assert_eq!(report[16..23], tag().uid());
+        assert_checksum(&report);
+    }
+
+    #[test]
+    fn reads_tag() {
+        let tag = tag();
+        let mut mcu = polling_mcu(Some(tag.clone()));
+        mcu.request(&[0x02, 0x06]);
+
+        let first = mcu.report();
+        assert_eq!(first[..4], [0x3a, 0x00, 0x07, 0x01]);
+        assert_eq!(first[15..22], tag.uid());
+        assert_eq!(first[22..37], magic::READ_HEADER);
+        assert_eq!(
+            first[67..67 + FIRST_READ_CHUNK],
+            tag.as_bytes()[..FIRST_READ_CHUNK]
+        );
+        assert_checksum(&first);
+
+        let second = mcu.report();
+        let rest = &tag.as_bytes()[FIRST_READ_CHUNK..];
+        assert_eq!(second[..4], [0x3a, 0x00, 0x07, 0x02]);
+        assert_eq!(second[7..7 + rest.len()], *rest);
+        assert_checksum(&second);
+
+        // Then back to reporting the tag
+        assert_eq!(mcu.report()[7], 0x09);
+    }
+
+    #[test]
+    fn no_read_without_tag() {
+        let mut mcu = polling_mcu(None);
+        mcu.request(&[0x02, 0x06]);
+        assert_eq!(mcu.report()[7], 0x01);
+    }
+}
@@ -0,0 +1,80 @@
+// Button index constants for the Nintendo Switch Online controllers. Each one
+// reports its buttons through the Pro Controller bit it is mapped to.
+
+pub mod nes {
+    use super::super::inputs;
+
+    pub const BUTTON_A: usize = inputs::BUTTON_A;
+    pub const BUTTON_B: usize = inputs::BUTTON_B;
+    pub const BUTTON_SELECT: usize = inputs::BUTTON_MINUS;
+    pub const BUTTON_START: usize = inputs::BUTTON_PLUS;
+    pub const BUTTON_L: usize = inputs::BUTTON_L;
+    pub const BUTTON_R: usize = inputs::BUTTON_R;
+    pub const BUTTON_DOWN: usize = inputs::BUTTON_DOWN;
+    pub const BUTTON_UP: usize = inputs::BUTTON_UP;
+    pub const BUTTON_RIGHT: usize = inputs::BUTTON_RIGHT;
+    pub const BUTTON_LEFT: usize = inputs::BUTTON_LEFT;
+}
+
+pub mod snes {
+    use super::super::inputs;
+
+    pub const BUTTON_A: usize = inputs::BUTTON_A;
+    pub const BUTTON_B: usize = inputs::BUTTON_B;
+    pub const BUTTON_X: usize = inputs::BUTTON_X;
+    pub const BUTTON_Y: usize = inputs::BUTTON_Y;
+    pub const BUTTON_L: usize = inputs::BUTTON_L;
+    pub const BUTTON_R: usize = inputs::BUTTON_R;
+    pub const BUTTON_ZL: usize = inputs::BUTTON_ZL;
+    pub const BUTTON_ZR: usize = inputs::BUTTON_ZR;
+    pub const BUTTON_SELECT: usize = inputs::BUTTON_MINUS;
+    pub const BUTTON_START: usize = inputs::BUTTON_PLUS;
+    pub const BUTTON_DOWN: usize = inputs::BUTTON_DOWN;
+    pub const BUTTON_UP: usize = inputs::BUTTON_UP;
+    pub const BUTTON_RIGHT: usize = inputs::BUTTON_RIGHT;
+    pub const BUTTON_LEFT: usize = inputs::BUTTON_LEFT;
+}
+
+pub mod n64 {
+    use super::super::inputs;
+
+    pub const BUTTON_A: usize = inputs::BUTTON_A;
+    pub const BUTTON_B: usize = inputs::BUTTON_B;
+    pub const BUTTON_C_UP: usize = inputs::BUTTON_Y;
+    pub const BUTTON_C_DOWN: usize = inputs::BUTTON_ZR;
+    pub const BUTTON_C_LEFT: usize = inputs::BUTTON_X;
+    pub const BUTTON_C_RIGHT: usize = inputs::BUTTON_MINUS;
+    pub const BUTTON_L: usize = inputs::BUTTON_L;
+    pub const BUTTON_R: usize = inputs::BUTTON_R;
+    pub const BUTTON_Z: usize = inputs::BUTTON_ZL;
+    pub const BUTTON_ZR: usize = inputs::BUTTON_L_STICK;
+    pub const BUTTON_START: usize = inputs::BUTTON_PLUS;
+    pub const BUTTON_HOME: usize = inputs::BUTTON_HOME;
+    pub const BUTTON_CAPTURE: usize = inputs::BUTTON_CAPTURE;
+    pub const BUTTON_DOWN: usize = inputs::BUTTON_DOWN;
+    pub const BUTTON_UP: usize = inputs::BUTTON_UP;
+    pub const BUTTON_RIGHT: usize = inputs::BUTTON_RIGHT;
+    pub const BUTTON_LEFT: usize = inputs::BUTTON_LEFT;
+
+    pub const AXIS_H: usize = inputs::AXIS_LH;
+    pub const AXIS_V: usize = inputs::AXIS_LV;
+}
+
+pub mod genesis {
+    use super::super::inputs;
+
+    pub const BUTTON_A: usize = inputs::BUTTON_A;
+    pub const BUTTON_B: usize = inputs::BUTTON_B;
+    pub const BUTTON_C: usize = inputs::BUTTON_R;
+    pub const BUTTON_X: usize = inputs::BUTTON_X;
+    pub const BUTTON_Y: usize = inputs::BUTTON_Y;
+    pub const BUTTON_Z: usize = inputs::BUTTON_L;
+    pub const BUTTON_MODE: usize = inputs::BUTTON_ZR;
+    pub const BUTTON_START: usize = inputs::BUTTON_PLUS;
+    pub const BUTTON_HOME: usize = inputs::BUTTON_HOME;
+    pub const BUTTON_CAPTURE: usize = inputs::BUTTON_CAPTURE;
+    pub const BUTTON_DOWN: usize = inputs::BUTTON_DOWN;
+    pub const BUTTON_UP: usize = inputs::BUTTON_UP;
+    pub const BUTTON_RIGHT: usize = inputs::BUTTON_RIGHT;
+    pub const BUTTON_LEFT: usize = inputs::BUTTON_LEFT;
+}
@@ -0,0 +1,77 @@
+use anyhow::{bail, Result};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Size of a raw NTAG215 dump, as produced by amiibo backup tools
+pub const SIZE: usize = 540;
+
+/// An NTAG215 tag (e.g. an amiibo) that can be placed on the emulated
+/// NFC reader
+#[derive(Clone)]
+pub struct Ntag {
+    data: Vec<u8>,
+}
+
+impl Ntag {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Ntag> {
+        if data.len() != SIZE {
+            bail!("NTAG215 dump must be {} bytes, got {}", SIZE, data.len());
+        }
+        Ok(Ntag { data })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Ntag> {
+        Ntag::from_bytes(fs::read(path)?)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The 7 byte UID, skipping the check byte stored after the first three
+    pub fn uid(&self) -> [u8; 7] {
+        let mut uid = [0; 7];
+        uid[..3].copy_from_slice(&self.data[0..3]);
+        uid[3..].copy_from_slice(&self.data[4..8]);
+        uid
+    }
+}
+
+impl fmt::Debug for Ntag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Ntag({})", hex::encode(self.uid()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag() -> Ntag {
+        let mut data = vec![0; SIZE];
+        data[..9].copy_from_slice(&[0x04, 0x11, 0x22, 0xbb, 0x33, 0x44, 0x55, 0x66, 0x77]);
+        Ntag::from_bytes(data).unwrap()
+    }
+
+    #[test]
+    fn rejects_wrong_size() {
+        assert!(Ntag::from_bytes(vec![0; SIZE - 1]).is_err());
+        assert!(Ntag::from_bytes(vec![0; SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn uid_skips_check_byte() {
+        assert_eq!(tag().uid(), [0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        assert_eq!(format!("{:?}", tag()), "Ntag(04112233445566)");
+    }
+
+    #[test]
+    fn loads_dumps() {
+        let path = std::env::temp_dir().join(format!("ntag-{}.bin", std::process::id()));
+        fs::write(&path, tag().as_bytes()).unwrap();
+        let loaded = Ntag::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.unwrap().as_bytes(), tag().as_bytes());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::controller::{Rumble, RumbleBand};
+
+/// Rumble data for both sides with both bands silent (320 Hz / 160 Hz)
+pub(super) const NEUTRAL: [u8; 8] = [0x00, 0x01, 0x40, 0x40, 0x00, 0x01, 0x40, 0x40];
+
+fn frequency(code: u16) -> f32 {
+    10.0 * 2f32.powf(code as f32 / 32.0)
+}
+
+// Inverse of the piecewise amplitude encoding documented at
+// https://github.com/dekuNukem/Nintendo_Switch_Reverse_Engineering/blob/master/rumble_data_table.md
+fn amplitude(code: u16) -> f32 {
+    let code = code as f32;
+    if code == 0.0 {
+        0.0
+    } else if code < 16.0 {
+        0.01 * 11.75f32.powf((code - 2.0) / 14.0)
+    } else if code < 32.0 {
+        2f32.powf(code / 16.0) / 17.0
+    } else {
+        (2f32.powf(code / 32.0) / 8.7).min(1.0)
+    }
+}
+
+/// Decodes the 4 bytes of HD rumble data for one side of the controller
+pub(super) fn decode(data: &[u8]) -> Rumble {
+    let hf = ((data[1] as u16 & 0x01) << 8) | data[0] as u16;
+    let hf_amp = data[1] as u16 >> 1;
+    let lf = data[2] as u16 & 0x7f;
+    let lf_amp = ((data[3] as u16) << 1 | data[2] as u16 >> 7).saturating_sub(0x80);
+
+    Rumble {
+        high: RumbleBand {
+            frequency: frequency((hf >> 2) + 0x60),
+            amplitude: amplitude(hf_amp),
+        },
+        low: RumbleBand {
+            frequency: frequency(lf + 0x40),
+            amplitude: amplitude(lf_amp),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_band(band: RumbleBand, frequency: f32, amplitude: f32) {
+        assert!(
+            (band.frequency - frequency).abs() < 0.01,
+            "{} Hz, expected {} Hz",
+            band.frequency,
+            frequency
+        );
+        assert!(
+            (band.amplitude - amplitude).abs() < 0.0001,
+            "amplitude {}, expected {}",
+            band.amplitude,
+            amplitude
+        );
+    }
+
+    #[test]
+    fn neutral_is_silent() {
+        for side in NEUTRAL.chunks(4) {
+            let rumble = decode(side);
+            assert_band(rumble.high, 320.0, 0.0);
+            assert_band(rumble.low, 160.0, 0.0);
+        }
+    }
+
+    #[test]
+    fn frequency_table_edges() {
+        let lowest = decode(&[0x00, 0x00, 0x00, 0x40]);
+        assert_band(lowest.high, 80.0, 0.0);
+        assert_band(lowest.low, 40.0, 0.0);
+
+        let highest = decode(&[0xfc, 0x01, 0x7f, 0x40]);
+        assert_band(highest.high, 1252.57, 0.0);
+        assert_band(highest.low, 626.28, 0.0);
+    }
+
+    #[test]
+    fn amplitude_table_edges() {
+        // Each piece of the encoding, and the loudest code
+        for &(code, amplitude) in &[
+            (0x01, 0.008_386),
+            (0x02, 0.01),
+            (0x10, 0.117_647),
+            (0x20, 0.229_885),
+            (0x64, 1.0),
+        ] {
+            let high = decode(&[0x00, (code << 1) as u8 | 0x01, 0x40, 0x40]);
+            assert_band(high.high, 320.0, amplitude);
+            assert_band(high.low, 160.0, 0.0);
+
+            let lf_amp = code + 0x80;
+            let low = decode(&[
+                0x00,
+                0x01,
+                (lf_amp as u8 & 0x01) << 7 | 0x40,
+                (lf_amp >> 1) as u8,
+            ]);
+            assert_band(low.high, 320.0, 0.0);
+            assert_band(low.low, 160.0, amplitude);
+        }
+        // Codes past the table are capped
+        assert_band(decode(&[0x00, 0xff, 0x40, 0x40]).high, 320.0, 1.0);
+    }
+}
@@ -0,0 +1,211 @@
+use super::device::DeviceType;
+use super::inputs::{AXIS_LH, AXIS_RH};
+use anyhow::{bail, Result};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Size of the SPI flash chip on a real Pro Controller (512 KiB)
+pub const SIZE: usize = 0x80000;
+/// Size of the region cleared by a single sector erase
+pub const SECTOR_SIZE: usize = 0x1000;
+
+mod magic {
+    pub const SERIAL_NUMBER: [u8; 16] = [0xff; 16];
+    pub const SENSOR_STICK_PARAMS: [u8; 24] = [
+        0x50, 0xfd, 0x00, 0x00, 0xc6, 0x0f, 0x0f, 0x30, 0x61, 0x96, 0x30, 0xf3, 0xd4, 0x14, 0x54,
+        0x41, 0x15, 0x54, 0xc7, 0x79, 0x9c, 0x33, 0x36, 0x63,
+    ];
+    pub const STICK_PARAMS_2: [u8; 18] = [
+        0x0f, 0x30, 0x61, 0x96, 0x30, 0xf3, 0xd4, 0x14, 0x54, 0x41, 0x15, 0x54, 0xc7, 0x79, 0x9c,
+        0x33, 0x36, 0x63,
+    ];
+    pub const CONFIG: [u8; 25] = [
+        0xba, 0x15, 0x62, 0x11, 0xb8, 0x7f, 0x29, 0x06, 0x5b, 0xff, 0xe7, 0x7e, 0x0e, 0x36, 0x56,
+        0x9e, 0x85, 0x60, 0xff, 0x32, 0x32, 0x32, 0xff, 0xff, 0xff,
+    ];
+    pub const CALIBRATION: [u8; 24] = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xb2, 0xa1,
+    ];
+    pub const SENSOR_CALIBRATION: [u8; 24] = [
+        0xbe, 0xff, 0x3e, 0x00, 0xf0, 0x01, 0x00, 0x40, 0x00, 0x40, 0x00, 0x40, 0xfe, 0xff, 0xfe,
+        0xff, 0x08, 0x00, 0xe7, 0x3b, 0xe7, 0x3b, 0xe7, 0x3b,
+    ];
+}
+
+/// Virtual SPI flash image, answering the reads, writes and sector erases
+/// the Switch issues through subcommands 0x10, 0x11 and 0x12
+#[derive(Clone)]
+pub struct SpiFlash {
+    data: Vec<u8>,
+}
+
+impl SpiFlash {
+    /// Builds an erased flash image populated with the factory data the
+    /// Switch reads during the handshake
+    pub fn create(body_col: [u8; 3]) -> SpiFlash {
+        SpiFlash::create_for(DeviceType::ProController, body_col)
+    }
+
+    /// Like `create`, but laid out for the given device: only the sticks the
+    /// device has are calibrated, and only a Pro Controller has grip colours
+    pub fn create_for(device_type: DeviceType, body_col: [u8; 3]) -> SpiFlash {
+        let mut flash = SpiFlash {
+            data: vec![0xff; SIZE],
+        };
+        flash.write(0x6000, &magic::SERIAL_NUMBER);
+        flash.write(0x6012, &[device_type.id()]);
+        flash.write(0x6020, &magic::SENSOR_CALIBRATION);
+        flash.write(0x603d, &magic::CONFIG);
+        if !device_type.has_axis(AXIS_LH) {
+            flash.write(0x603d, &[0xff; 9]);
+        }
+        if !device_type.has_axis(AXIS_RH) {
+            flash.write(0x6046, &[0xff; 9]);
+        }
+        let grip_col = match device_type {
+            DeviceType::ProController => body_col,
+            _ => [0xff; 3],
+        };
+        flash.write(0x6050, &[body_col, [0, 0, 0], grip_col, grip_col].concat());
+        flash.write(0x6080, &magic::SENSOR_STICK_PARAMS);
+        flash.write(0x6098, &magic::STICK_PARAMS_2);
+        flash.write(0x8010, &magic::CALIBRATION);
+        flash.write(0x8028, &magic::SENSOR_CALIBRATION);
+        flash
+    }
+
+    /// Wraps a raw flash image, which must be exactly 512 KiB
+    pub fn from_bytes(data: Vec<u8>) -> Result<SpiFlash> {
+        if data.len() != SIZE {
+            bail!("SPI flash image must be {} bytes, got {}", SIZE, data.len());
+        }
+        Ok(SpiFlash { data })
+    }
+
+    /// Loads a dump of a real controller's SPI flash
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SpiFlash> {
+        SpiFlash::from_bytes(fs::read(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, &self.data)?;
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Reads `len` bytes starting at `addr`. Bytes past the end of the chip
+    /// read back as erased (0xff) rather than failing.
+    pub fn read(&self, addr: u32, len: usize) -> Vec<u8> {
+        (addr as usize..addr as usize + len)
+            .map(|i| self.data.get(i).copied().unwrap_or(0xff))
+            .collect()
+    }
+
+    /// Writes `data` at `addr`, returning false if it would run off the chip
+    pub fn write(&mut self, addr: u32, data: &[u8]) -> bool {
+        let start = addr as usize;
+        match self.data.get_mut(start..start + data.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Resets the 4 KiB sector containing `addr` to 0xff
+    pub fn erase_sector(&mut self, addr: u32) -> bool {
+        let start = addr as usize & !(SECTOR_SIZE - 1);
+        match self.data.get_mut(start..start + SECTOR_SIZE) {
+            Some(sector) => {
+                sector.iter_mut().for_each(|b| *b = 0xff);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl fmt::Debug for SpiFlash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SpiFlash({} bytes)", self.data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_layout() {
+        let flash = SpiFlash::create([0x12, 0x34, 0x56]);
+        assert_eq!(flash.as_bytes().len(), SIZE);
+        assert_eq!(flash.read(0x6000, 16), magic::SERIAL_NUMBER);
+        assert_eq!(flash.read(0x6012, 1), [DeviceType::ProController.id()]);
+        assert_eq!(
+            flash.read(0x6050, 12),
+            [0x12, 0x34, 0x56, 0, 0, 0, 0x12, 0x34, 0x56, 0x12, 0x34, 0x56]
+        );
+        // The last bytes of the stick calibration block are the colours
+        assert_eq!(flash.read(0x603d, 18), magic::CONFIG[..18]);
+        assert_eq!(flash.read(0x8010, 24), magic::CALIBRATION);
+        // Everything else is erased
+        assert_eq!(flash.read(0x0000, 4), [0xff; 4]);
+    }
+
+    #[test]
+    fn joycon_layout() {
+        let flash = SpiFlash::create_for(DeviceType::JoyConL, [0x12, 0x34, 0x56]);
+        assert_eq!(flash.read(0x6012, 1), [DeviceType::JoyConL.id()]);
+        // Only the left stick is calibrated, and there are no grips
+        assert_eq!(flash.read(0x603d, 9), magic::CONFIG[..9]);
+        assert_eq!(flash.read(0x6046, 9), [0xff; 9]);
+        assert_eq!(flash.read(0x6056, 6), [0xff; 6]);
+    }
+
+    #[test]
+    fn reads_past_end_are_erased() {
+        let mut flash = SpiFlash::create([0; 3]);
+        assert!(flash.write(SIZE as u32 - 2, &[1, 2]));
+        assert_eq!(flash.read(SIZE as u32 - 2, 4), [1, 2, 0xff, 0xff]);
+        assert_eq!(flash.read(u32::MAX - 1, 2), [0xff, 0xff]);
+    }
+
+    #[test]
+    fn writes_off_the_chip_are_refused() {
+        let mut flash = SpiFlash::create([0; 3]);
+        assert!(!flash.write(SIZE as u32 - 1, &[1, 2]));
+        assert_eq!(flash.read(SIZE as u32 - 1, 1), [0xff]);
+    }
+
+    #[test]
+    fn erase_sector_clears_whole_sector() {
+        let mut flash = SpiFlash::create([0; 3]);
+        assert!(flash.write(0x2fff, &[0]));
+        assert!(flash.write(0x3000, &[0; 16]));
+        assert!(flash.write(0x4000, &[0]));
+        assert!(flash.erase_sector(0x3abc));
+        assert_eq!(flash.read(0x2fff, 1), [0]);
+        assert_eq!(flash.read(0x3000, 16), [0xff; 16]);
+        assert_eq!(flash.read(0x4000, 1), [0]);
+        assert!(!flash.erase_sector(SIZE as u32));
+    }
+
+    #[test]
+    fn loads_dumps() {
+        assert!(SpiFlash::from_bytes(vec![0; SIZE - 1]).is_err());
+
+        let path = std::env::temp_dir().join(format!("spi_flash-{}.bin", std::process::id()));
+        let mut flash = SpiFlash::create([0x12, 0x34, 0x56]);
+        flash.write(0x10, &[1, 2, 3]);
+        flash.save(&path).unwrap();
+        let loaded = SpiFlash::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(loaded.unwrap().as_bytes(), flash.as_bytes());
+    }
+}
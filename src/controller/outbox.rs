@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+#[derive(Debug, Default)]
+struct Pending {
+    replies: VecDeque<Vec<u8>>,
+    report: Option<Vec<u8>>,
+    closed: bool,
+}
+
+/// Reports waiting to be written to a HID device. Replies to the host are
+/// written in order, ahead of input reports. Only the latest input report
+/// is kept: the host can poll less often than reports are streamed, and
+/// queueing them would only delay newer input behind stale input.
+///
+/// Nothing here blocks for longer than it takes to take the lock, so it's
+/// safe to use while holding a controller's state lock.
+#[derive(Debug, Default)]
+pub(crate) struct Outbox {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+impl Outbox {
+    /// Queues a reply to the host
+    pub(crate) fn reply(&self, reply: Vec<u8>) {
+        self.pending.lock().unwrap().replies.push_back(reply);
+        self.ready.notify_one();
+    }
+
+    /// Replaces the input report waiting to be written, if any
+    pub(crate) fn report(&self, report: Vec<u8>) {
+        self.pending.lock().unwrap().report = Some(report);
+        self.ready.notify_one();
+    }
+
+    /// Drops anything waiting and makes `next` return `None` from now on
+    pub(crate) fn close(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending = Pending {
+            closed: true,
+            ..Default::default()
+        };
+        self.ready.notify_all();
+    }
+
+    /// Waits for the next report to write, or returns `None` once closed
+    pub(crate) fn next(&self) -> Option<Vec<u8>> {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            if pending.closed {
+                return None;
+            }
+            if let Some(reply) = pending.replies.pop_front() {
+                return Some(reply);
+            }
+            if let Some(report) = pending.report.take() {
+                return Some(report);
+            }
+            pending = self.ready.wait(pending).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn replies_go_ahead_of_reports() {
+        let outbox = Outbox::default();
+        outbox.report(vec![0x30, 1]);
+        outbox.reply(vec![0x21, 1]);
+        outbox.report(vec![0x30, 2]);
+        outbox.reply(vec![0x21, 2]);
+        assert_eq!(outbox.next(), Some(vec![0x21, 1]));
+        assert_eq!(outbox.next(), Some(vec![0x21, 2]));
+        // Only the latest report is kept
+        assert_eq!(outbox.next(), Some(vec![0x30, 2]));
+    }
+
+    #[test]
+    fn next_waits_for_a_report() {
+        let outbox = Arc::new(Outbox::default());
+        let writer = {
+            let outbox = outbox.clone();
+            thread::spawn(move || outbox.next())
+        };
+        outbox.report(vec![0x30]);
+        assert_eq!(writer.join().unwrap(), Some(vec![0x30]));
+    }
+
+    #[test]
+    fn close_stops_the_writer() {
+        let outbox = Arc::new(Outbox::default());
+        let writer = {
+            let outbox = outbox.clone();
+            thread::spawn(move || {
+                let mut written = 0;
+                while outbox.next().is_some() {
+                    written += 1;
+                }
+                written
+            })
+        };
+        outbox.close();
+        outbox.report(vec![0x30]);
+        assert_eq!(writer.join().unwrap(), 0);
+        assert_eq!(outbox.next(), None);
+    }
+}
@@ -1,26 +1,44 @@
 use std::fmt;
-use std::fs::{create_dir_all, read_dir, remove_dir, remove_file, File};
-use std::io::prelude::*;
-use std::io::Result;
+use std::fs::{create_dir_all, read_dir, remove_dir, remove_file, symlink_metadata, write};
+use std::io;
 use std::os::unix::fs::symlink;
-use std::path::Path;
-use std::process::Command;
-
+use std::path::{Path, PathBuf};
+
+mod builder;
+mod error;
+pub mod gc_adapter;
+mod handle;
+pub mod hid;
+pub mod hori_pad;
+pub mod keyboard;
+mod load;
+mod node;
+pub mod ns_joycon;
 pub mod ns_procon;
-
+pub mod nso;
+mod reconcile;
+mod udc;
+mod validate;
+
+pub use builder::GadgetBuilder;
+pub use error::GadgetError;
+pub use handle::GadgetHandle;
+pub use load::{gadgets, LiveGadget};
+pub use reconcile::Change;
+pub use udc::{Udc, UdcState};
+pub use validate::ValidationError;
+
+type Result<T> = std::result::Result<T, GadgetError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum Speed {
     LowSpeed,
+    #[default]
     FullSpeed,
     HighSpeed,
     SuperSpeed,
 }
 
-impl Default for Speed {
-    fn default() -> Self {
-        Speed::FullSpeed
-    }
-}
-
 impl fmt::Display for Speed {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -36,7 +54,7 @@ impl fmt::Display for Speed {
     }
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Config {
     attributes: u8,
     max_power: u32,
@@ -45,176 +63,649 @@ pub struct Config {
     hid_functions: Vec<u32>,
 }
 
-#[derive(Default, Clone)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct HIDFunction {
-    pub(in crate) protocol: u32,
-    pub(in crate) report_desc: Vec<u8>,
-    pub(in crate) report_length: u32,
-    pub(in crate) subclass: u32,
+    pub(crate) protocol: u32,
+    pub(crate) report_desc: Vec<u8>,
+    pub(crate) report_length: u32,
+    pub(crate) subclass: u32,
 }
 
-#[derive(Default)]
+impl Config {
+    pub fn new(description: &str) -> Config {
+        Config {
+            attributes: 0x80,
+            max_power: 500,
+            description: description.to_string(),
+            hid_functions: Vec::new(),
+        }
+    }
+
+    /// bmAttributes: bit 7 must be set, bit 6 is self powered and bit 5
+    /// remote wakeup
+    pub fn attributes(mut self, attributes: u8) -> Config {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Maximum current draw in mA
+    pub fn max_power(mut self, max_power: u32) -> Config {
+        self.max_power = max_power;
+        self
+    }
+
+    /// Adds the gadget's HID function at `index` to this configuration
+    pub fn hid_function(mut self, index: u32) -> Config {
+        self.hid_functions.push(index);
+        self
+    }
+}
+
+impl HIDFunction {
+    pub fn new(report_desc: Vec<u8>, report_length: u32) -> HIDFunction {
+        HIDFunction {
+            report_desc,
+            report_length,
+            ..Default::default()
+        }
+    }
+
+    pub fn protocol(mut self, protocol: u32) -> HIDFunction {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn subclass(mut self, subclass: u32) -> HIDFunction {
+        self.subclass = subclass;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Gadget {
-    pub(in crate) max_speed: Speed,
-    pub(in crate) device_class: u8,
-    pub(in crate) device_sub_class: u8,
-    pub(in crate) device_protocol: u8,
-    pub(in crate) device_max_packet_size: u8,
+    pub(crate) max_speed: Speed,
+    pub(crate) device_class: u8,
+    pub(crate) device_sub_class: u8,
+    pub(crate) device_protocol: u8,
+    pub(crate) device_max_packet_size: u8,
+
+    pub(crate) device_version: u32,
+    pub(crate) usb_version: u32,
+    pub(crate) product_id: u32,
+    pub(crate) vendor_id: u32,
+
+    pub(crate) configs: Vec<Config>,
+    pub(crate) hid_functions: Vec<HIDFunction>,
+
+    pub(crate) serialnumber: String,
+    pub(crate) product: String,
+    pub(crate) manufacturer: String,
+}
+
+/// Where the kernel exposes USB gadgets: the configfs `usb_gadget`
+/// directory and the UDC class in sysfs, plus where HID functions' device
+/// nodes are looked up. Pointing these somewhere else allows writing a
+/// gadget tree to a scratch directory, or running on a system with
+/// configfs mounted elsewhere.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GadgetEnv {
+    configfs: PathBuf,
+    udc_class: PathBuf,
+    /// The sysfs directory of character devices by major:minor, used to
+    /// find the device node of a HID function
+    char_devices: PathBuf,
+    dev: PathBuf,
+}
+
+impl Default for GadgetEnv {
+    fn default() -> Self {
+        GadgetEnv {
+            configfs: PathBuf::from("/sys/kernel/config/usb_gadget"),
+            udc_class: PathBuf::from("/sys/class/udc"),
+            char_devices: PathBuf::from("/sys/dev/char"),
+            dev: PathBuf::from("/dev"),
+        }
+    }
+}
+
+impl GadgetEnv {
+    /// An environment with its own configfs and UDC class directories.
+    /// Device nodes are still looked up in /sys/dev/char and /dev; use
+    /// `set_char_devices` and `set_dev` to move those too.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(configfs: P, udc_class: Q) -> GadgetEnv {
+        GadgetEnv {
+            configfs: configfs.as_ref().to_path_buf(),
+            udc_class: udc_class.as_ref().to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    pub fn configfs(&self) -> &Path {
+        &self.configfs
+    }
 
-    pub(in crate) device_version: u32,
-    pub(in crate) usb_version: u32,
-    pub(in crate) product_id: u32,
-    pub(in crate) vendor_id: u32,
+    pub fn udc_class(&self) -> &Path {
+        &self.udc_class
+    }
+
+    pub fn char_devices(&self) -> &Path {
+        &self.char_devices
+    }
+
+    /// Sets the sysfs directory of character devices by major:minor
+    pub fn set_char_devices<P: AsRef<Path>>(&mut self, char_devices: P) {
+        self.char_devices = char_devices.as_ref().to_path_buf();
+    }
 
-    pub(in crate) configs: Vec<Config>,
-    pub(in crate) hid_functions: Vec<HIDFunction>,
+    pub fn dev(&self) -> &Path {
+        &self.dev
+    }
+
+    /// Sets the directory device nodes are created in
+    pub fn set_dev<P: AsRef<Path>>(&mut self, dev: P) {
+        self.dev = dev.as_ref().to_path_buf();
+    }
 
-    pub(in crate) serialnumber: String,
-    pub(in crate) product: String,
-    pub(in crate) manufacturer: String,
+    /// The directory of the gadget named `name`. The name must be a single
+    /// path component, so it can't point outside the configfs root.
+    pub fn gadget_path(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name == "." || name.contains('/') || name.contains("..") {
+            return Err(GadgetError::InvalidName(name.to_string()));
+        }
+        Ok(self.configfs.join(name))
+    }
+
+    fn check_mounted(&self) -> Result<()> {
+        if !self.configfs.is_dir() {
+            return Err(GadgetError::ConfigfsNotMounted(self.configfs.clone()));
+        }
+        Ok(())
+    }
+
+    /// Unbinds the gadget and binds it again to the first free UDC. Use
+    /// `bind` to choose the UDC.
+    pub fn activate(&self, name: &str) -> Result<()> {
+        self.deactivate(name)?;
+        self.bind_any(name)?;
+        Ok(())
+    }
+
+    /// Unbinds the gadget from its UDC, if it's bound
+    pub fn deactivate(&self, name: &str) -> Result<()> {
+        if self.bound_udc(name)?.is_none() {
+            return Ok(());
+        }
+        let path = self.gadget_path(name)?.join("UDC");
+        write(&path, "\n").map_err(|e| GadgetError::io(&path, e))
+    }
+
+    pub fn reset(&self, name: &str) -> Result<()> {
+        self.activate(name)
+    }
+
+    /// Completely removes the gadget at <configfs>/<name>
+    /// This function is kinda gross since you can't just rm -rf but have to
+    /// individually remove all of the internals, in order
+    pub fn remove_config(&self, name: &str) -> Result<()> {
+        self.check_mounted()?;
+        let base_path = self.gadget_path(name)?;
+        self.deactivate(name)?;
+
+        for config in list_dir(&base_path.join("configs"))? {
+            if !is_real_dir(&config) {
+                remove_node(&config)?;
+                continue;
+            }
+            for entry in list_dir(&config)? {
+                let is_link = symlink_metadata(&entry)
+                    .map(|m| m.file_type().is_symlink())
+                    .unwrap_or(false);
+                if is_link {
+                    remove_file(&entry).map_err(|e| GadgetError::io(&entry, e))?;
+                }
+            }
+            for lang in list_dir(&config.join("strings"))? {
+                remove_node(&lang)?;
+            }
+            remove_node(&config)?;
+        }
+
+        for function in list_dir(&base_path.join("functions"))? {
+            remove_node(&function)?;
+        }
+
+        for lang in list_dir(&base_path.join("strings"))? {
+            remove_node(&lang)?;
+        }
+
+        remove_node(&base_path)
+    }
 }
 
 pub fn activate(name: &str) -> Result<()> {
-    let path = Path::new("/sys/kernel/config/usb_gadget")
-        .join(name)
-        .join("UDC");
-    Command::new("sh")
-        .arg("-c")
-        .arg(format!("echo \"\" > {}", path.display()))
-        .output()?;
-    let output = Command::new("ls").arg("/sys/class/udc").output()?;
-    let mut udc = File::create(&path)?;
-    udc.write_all(&output.stdout)?;
-    Ok(())
+    GadgetEnv::default().activate(name)
 }
 
 pub fn deactivate(name: &str) -> Result<()> {
-    let path = Path::new("/sys/kernel/config/usb_gadget")
-        .join(name)
-        .join("UDC");
-    Command::new("sh")
-        .arg("-c")
-        .arg(format!("echo \"\" > {}", path.display()))
-        .output()?;
-    Ok(())
+    GadgetEnv::default().deactivate(name)
 }
 
 pub fn reset(name: &str) -> Result<()> {
-    let _ = deactivate(name);
-    activate(name)
+    GadgetEnv::default().reset(name)
 }
 
 /// Completely removes the gadget at /sys/kernel/config/usb_gadget/<name>
-/// This function is kinda gross since you can't just rm -rf but have to
-/// individually remove all of the internals
 pub fn remove_config(name: &str) -> Result<()> {
-    let base_path = Path::new("/sys/kernel/config/usb_gadget").join(name);
-
-    for config in read_dir(base_path.join("configs"))? {
-        let config = config?;
-        for function in read_dir(config.path())? {
-            let function = function?;
-            if function.path().is_dir() {
-                let _ = remove_file(function.path());
-            }
+    GadgetEnv::default().remove_config(name)
+}
+
+/// The subdirectories of a gadget directory, or nothing if it doesn't exist
+fn list_dir(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for entry in read_dir(path).map_err(|e| GadgetError::io(path, e))? {
+        let entry = entry.map_err(|e| GadgetError::io(path, e))?;
+        let is_dir = symlink_metadata(entry.path())
+            .map(|m| m.is_dir() || m.file_type().is_symlink())
+            .unwrap_or(false);
+        if is_dir {
+            entries.push(entry.path());
         }
+    }
+    Ok(entries)
+}
 
-        for lang in read_dir(config.path().join("strings"))? {
-            let lang = lang?;
-            if lang.path().is_dir() {
-                let _ = remove_dir(lang.path());
+/// The directories configfs creates along with a gadget, config or function
+const DEFAULT_GROUPS: [&str; 3] = ["configs", "functions", "strings"];
+
+/// Removes a gadget directory, or the link or file at `path`, without
+/// following links. Configfs drops the attribute files and default groups
+/// like `strings` along with the directory, but in a plain directory tree
+/// (e.g. one written to a scratch directory) they're real files and
+/// directories that have to go first. Only that layout is removed: a
+/// directory holding anything else is left alone and reported.
+fn remove_node(path: &Path) -> Result<()> {
+    let metadata = match symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(GadgetError::io(path, e)),
+    };
+    if !metadata.is_dir() {
+        return remove_file(path).map_err(|e| GadgetError::io(path, e));
+    }
+    match remove_dir(path) {
+        Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => {
+            remove_attribute_files(path)?;
+            for group in DEFAULT_GROUPS.iter().map(|group| path.join(group)) {
+                if !is_real_dir(&group) {
+                    continue;
+                }
+                if group.ends_with("strings") {
+                    for lang in list_dir(&group)?.iter().filter(|l| is_real_dir(l)) {
+                        remove_attribute_files(lang)?;
+                        remove_dir(lang).map_err(|e| GadgetError::io(lang, e))?;
+                    }
+                }
+                remove_dir(&group).map_err(|e| GadgetError::io(&group, e))?;
             }
+            remove_dir(path).map_err(|e| GadgetError::io(path, e))
         }
-
-        let _ = remove_dir(config.path());
+        result => result.map_err(|e| GadgetError::io(path, e)),
     }
+}
 
-    for function in read_dir(base_path.join("functions"))? {
-        let function = function?;
-        let _ = remove_dir(function.path());
-    }
+/// Whether `path` is a directory rather than a link to one
+fn is_real_dir(path: &Path) -> bool {
+    symlink_metadata(path).map(|m| m.is_dir()).unwrap_or(false)
+}
 
-    for lang in read_dir(base_path.join("strings"))? {
-        let lang = lang?;
-        if !lang.path().is_dir() {
-            let _ = remove_dir(lang.path());
+/// Removes the regular files directly in `path`, leaving links and
+/// directories
+fn remove_attribute_files(path: &Path) -> Result<()> {
+    for entry in read_dir(path).map_err(|e| GadgetError::io(path, e))? {
+        let entry = entry.map_err(|e| GadgetError::io(path, e))?;
+        let is_file = entry.file_type().map(|t| t.is_file()).unwrap_or(false);
+        if is_file {
+            let file = entry.path();
+            remove_file(&file).map_err(|e| GadgetError::io(&file, e))?;
         }
     }
+    Ok(())
+}
 
-    let _ = remove_dir(base_path);
+/// Attribute files and their contents, relative to the directory they're
+/// written to
+type Attributes = Vec<(&'static str, Vec<u8>)>;
 
-    Ok(())
+fn hex_byte(value: u8) -> Vec<u8> {
+    format!("{:#04x}", value).into_bytes()
 }
 
-fn write_file(path: &Path, name: &str, contents: &[u8]) -> Result<()> {
-    create_dir_all(&path)?;
-    let file_name = Path::join(path, name);
-    let mut file = File::create(&file_name)?;
-    file.write_all(contents).expect("couldn't write");
-    Ok(())
+fn hex_int(value: u32) -> Vec<u8> {
+    format!("{:#06x}", value).into_bytes()
 }
 
-fn write_file_str(path: &Path, name: &str, contents: &str) -> Result<()> {
-    write_file(path, name, contents.as_bytes())
+fn write_attribute(dir: &Path, name: &str, contents: &[u8]) -> Result<()> {
+    let path = dir.join(name);
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(|e| GadgetError::io(parent, e))?;
+    }
+    write(&path, contents).map_err(|e| GadgetError::attribute(&path, contents, e))
 }
 
-fn write_file_byte(path: &Path, name: &str, contents: u8) -> Result<()> {
-    write_file_str(path, name, &format!("{:#04x}", contents))
+/// The directory of the HID function at `index`, relative to the gadget
+fn function_dir(index: usize) -> String {
+    format!("functions/hid.usb.{}", index)
 }
 
-fn write_file_int(path: &Path, name: &str, contents: u32) -> Result<()> {
-    write_file_str(path, name, &format!("{:#06x}", contents))
+/// The directory of the config at `index`, relative to the gadget. Config
+/// numbers start at 1.
+fn config_dir(index: usize) -> String {
+    format!("configs/c.{}", index + 1)
+}
+
+fn link_function(base_path: &Path, config: usize, function: usize) -> Result<()> {
+    let hid_path = base_path.join(function_dir(function));
+    let link_path = base_path
+        .join(config_dir(config))
+        .join(format!("hid.usb.{}", function));
+    symlink(&hid_path, &link_path).map_err(|e| GadgetError::io(&link_path, e))
+}
+
+fn unlink_function(base_path: &Path, config: usize, function: usize) -> Result<()> {
+    let link_path = base_path
+        .join(config_dir(config))
+        .join(format!("hid.usb.{}", function));
+    remove_file(&link_path).map_err(|e| GadgetError::io(&link_path, e))
+}
+
+impl HIDFunction {
+    fn attribute_files(&self) -> Attributes {
+        vec![
+            ("protocol", hex_int(self.protocol)),
+            ("report_desc", self.report_desc.clone()),
+            ("report_length", hex_int(self.report_length)),
+            ("subclass", hex_int(self.subclass)),
+        ]
+    }
+}
+
+impl Config {
+    fn attribute_files(&self) -> Attributes {
+        vec![
+            ("bmAttributes", hex_byte(self.attributes)),
+            ("MaxPower", hex_int(self.max_power)),
+            (
+                "strings/0x409/configuration",
+                self.description.clone().into_bytes(),
+            ),
+        ]
+    }
 }
 
 impl Gadget {
-    pub fn create_config(&self, name: &str) -> Result<()> {
-        // Remove existing configuration
-        let _ = remove_config(name);
+    pub fn builder(vendor_id: u32, product_id: u32) -> GadgetBuilder {
+        GadgetBuilder::new(vendor_id, product_id)
+    }
+
+    pub fn vendor_id(&self) -> u32 {
+        self.vendor_id
+    }
 
-        let base_path = Path::new("/sys/kernel/config/usb_gadget").join(name);
+    pub fn product_id(&self) -> u32 {
+        self.product_id
+    }
 
-        write_file_str(&base_path, "max_speed", &self.max_speed.to_string())?;
-        write_file_byte(&base_path, "bDeviceClass", self.device_class)?;
-        write_file_byte(&base_path, "bDeviceSubClass", self.device_sub_class)?;
-        write_file_byte(&base_path, "bDeviceProtocol", self.device_protocol)?;
-        write_file_byte(&base_path, "bMaxPacketSize0", self.device_max_packet_size)?;
+    pub fn serial_number(&self) -> &str {
+        &self.serialnumber
+    }
 
-        write_file_int(&base_path, "bcdDevice", self.device_version)?;
-        write_file_int(&base_path, "bcdUSB", self.usb_version)?;
-        write_file_int(&base_path, "idProduct", self.product_id)?;
-        write_file_int(&base_path, "idVendor", self.vendor_id)?;
+    pub fn product(&self) -> &str {
+        &self.product
+    }
 
-        let strings_path = Path::join(&base_path, "strings/0x409");
+    pub fn manufacturer(&self) -> &str {
+        &self.manufacturer
+    }
 
-        write_file_str(&strings_path, "serialnumber", &self.serialnumber)?;
-        write_file_str(&strings_path, "product", &self.product)?;
-        write_file_str(&strings_path, "manufacturer", &self.manufacturer)?;
+    pub fn hid_functions(&self) -> &[HIDFunction] {
+        &self.hid_functions
+    }
 
-        for i in 0..self.hid_functions.len() {
-            let hid_path = Path::join(&base_path, format!("functions/hid.usb.{}", i));
-            let function = &self.hid_functions[i];
-            write_file_int(&hid_path, "protocol", function.protocol)?;
-            write_file(&hid_path, "report_desc", &function.report_desc)?;
-            write_file_int(&hid_path, "report_length", function.report_length)?;
-            write_file_int(&hid_path, "subclass", function.subclass)?;
-        }
+    fn attribute_files(&self) -> Attributes {
+        vec![
+            ("max_speed", self.max_speed.to_string().into_bytes()),
+            ("bDeviceClass", hex_byte(self.device_class)),
+            ("bDeviceSubClass", hex_byte(self.device_sub_class)),
+            ("bDeviceProtocol", hex_byte(self.device_protocol)),
+            ("bMaxPacketSize0", hex_byte(self.device_max_packet_size)),
+            ("bcdDevice", hex_int(self.device_version)),
+            ("bcdUSB", hex_int(self.usb_version)),
+            ("idProduct", hex_int(self.product_id)),
+            ("idVendor", hex_int(self.vendor_id)),
+            (
+                "strings/0x409/serialnumber",
+                self.serialnumber.clone().into_bytes(),
+            ),
+            ("strings/0x409/product", self.product.clone().into_bytes()),
+            (
+                "strings/0x409/manufacturer",
+                self.manufacturer.clone().into_bytes(),
+            ),
+        ]
+    }
+
+    pub fn create_config(&self, name: &str) -> Result<()> {
+        self.create_config_in(&GadgetEnv::default(), name)
+    }
+
+    /// Like `create_config`, but under the configfs root of `env`
+    pub fn create_config_in(&self, env: &GadgetEnv, name: &str) -> Result<()> {
+        self.validate()?;
+        env.check_mounted()?;
+
+        // Remove existing configuration
+        env.remove_config(name)?;
 
-        for i in 1..=self.configs.len() {
-            let config_path = Path::join(&base_path, format!("configs/c.{}", i));
-            let config = &self.configs[i - 1];
-            write_file_byte(&config_path, "bmAttributes", config.attributes)?;
-            write_file_int(&config_path, "MaxPower", config.max_power)?;
+        let base_path = env.gadget_path(name)?;
 
-            let string_path = Path::join(&config_path, "/strings/0x409");
-            write_file_str(&string_path, "idVendor", &config.description)?;
+        for (name, contents) in self.attribute_files() {
+            write_attribute(&base_path, name, &contents)?;
+        }
 
+        for (i, function) in self.hid_functions.iter().enumerate() {
+            let hid_path = base_path.join(function_dir(i));
+            for (name, contents) in function.attribute_files() {
+                write_attribute(&hid_path, name, &contents)?;
+            }
+        }
+
+        for (i, config) in self.configs.iter().enumerate() {
+            let config_path = base_path.join(config_dir(i));
+            for (name, contents) in config.attribute_files() {
+                write_attribute(&config_path, name, &contents)?;
+            }
             for hid in &config.hid_functions {
-                let hid_path = Path::join(&base_path, format!("functions/hid.usb.{}", hid));
-                let link_path = Path::join(&config_path, format!("hid.usb.{}", hid));
-                symlink(&hid_path, &link_path)?;
+                link_function(&base_path, i, *hid as usize)?;
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A scratch configfs root, UDC class directory with one UDC, and
+    /// device directories, removed when dropped
+    pub(super) struct TestEnv {
+        root: PathBuf,
+        pub(super) env: GadgetEnv,
+    }
+
+    impl TestEnv {
+        pub(super) fn new(test: &str) -> TestEnv {
+            let root =
+                std::env::temp_dir().join(format!("usb_gadget-{}-{}", std::process::id(), test));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("usb_gadget")).unwrap();
+            fs::create_dir_all(root.join("udc/dummy_udc.0")).unwrap();
+            let mut env = GadgetEnv::new(root.join("usb_gadget"), root.join("udc"));
+            env.set_char_devices(root.join("char"));
+            env.set_dev(root.join("dev"));
+            TestEnv { env, root }
+        }
+    }
+
+    impl Drop for TestEnv {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn writes_ns_procons() {
+        let test = TestEnv::new("writes_ns_procons");
+        ns_procon::ns_procons()
+            .create_config_in(&test.env, "procons")
+            .unwrap();
+
+        let base_path = test.env.gadget_path("procons").unwrap();
+        let read = |path: &str| fs::read_to_string(base_path.join(path)).unwrap();
+        assert_eq!(read("idVendor"), "0x057e");
+        assert_eq!(read("idProduct"), "0x2009");
+        assert_eq!(read("bcdDevice"), "0x0210");
+        assert_eq!(read("bMaxPacketSize0"), "0x40");
+        assert_eq!(read("max_speed"), "full-speed");
+        assert_eq!(read("strings/0x409/product"), "Pro Controller");
+        assert_eq!(read("strings/0x409/manufacturer"), "Nintendo Co., Ltd");
+        assert_eq!(read("configs/c.1/bmAttributes"), "0x80");
+        assert_eq!(
+            read("configs/c.1/strings/0x409/configuration"),
+            "HID Configuration"
+        );
+
+        for i in 0..4 {
+            let hid_path = base_path.join(format!("functions/hid.usb.{}", i));
+            assert_eq!(
+                fs::read(hid_path.join("report_desc")).unwrap(),
+                ns_procon::report_desc()
+            );
+            assert_eq!(
+                fs::read_to_string(hid_path.join("report_length")).unwrap(),
+                "0x0040"
+            );
+            let link_path = base_path.join(format!("configs/c.1/hid.usb.{}", i));
+            assert_eq!(fs::read_link(link_path).unwrap(), hid_path);
+        }
+    }
+
+    #[test]
+    fn bind_any_skips_udcs_with_a_driver() {
+        let test = TestEnv::new("bind_any_skips_udcs_with_a_driver");
+        let udc_class = test.env.udc_class().to_path_buf();
+        fs::write(udc_class.join("dummy_udc.0/function"), "g_ether\n").unwrap();
+        keyboard::keyboard()
+            .create_config_in(&test.env, "keyboard")
+            .unwrap();
+        assert!(matches!(
+            test.env.bind_any("keyboard"),
+            Err(GadgetError::NoFreeUdc)
+        ));
+
+        fs::create_dir(udc_class.join("dummy_udc.1")).unwrap();
+        fs::write(udc_class.join("dummy_udc.1/function"), "").unwrap();
+        assert_eq!(test.env.bind_any("keyboard").unwrap(), "dummy_udc.1");
+    }
+
+    #[test]
+    fn rejects_names_outside_configfs() {
+        let test = TestEnv::new("rejects_names_outside_configfs");
+        for name in &["", ".", "..", "../procons", "procons/..", "a/b"] {
+            assert!(matches!(
+                test.env.gadget_path(name),
+                Err(GadgetError::InvalidName(_))
+            ));
+            assert!(matches!(
+                test.env.remove_config(name),
+                Err(GadgetError::InvalidName(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn remove_config_doesnt_follow_links() {
+        let test = TestEnv::new("remove_config_doesnt_follow_links");
+        keyboard::keyboard()
+            .create_config_in(&test.env, "keyboard")
+            .unwrap();
+        let outside = test.root.join("outside");
+        fs::create_dir_all(outside.join("0x409")).unwrap();
+        fs::write(outside.join("0x409/product"), "keep").unwrap();
+        let strings_path = test.env.gadget_path("keyboard").unwrap().join("strings");
+        symlink(outside.join("0x409"), strings_path.join("0x40c")).unwrap();
+
+        test.env.remove_config("keyboard").unwrap();
+        assert!(!test.env.gadget_path("keyboard").unwrap().exists());
+        assert_eq!(
+            fs::read_to_string(outside.join("0x409/product")).unwrap(),
+            "keep"
+        );
+    }
+
+    #[test]
+    fn remove_node_leaves_unknown_directories() {
+        let test = TestEnv::new("remove_node_leaves_unknown_directories");
+        let function_path = test.root.join("hid.usb.0");
+        fs::create_dir_all(function_path.join("unknown")).unwrap();
+        fs::write(function_path.join("report_length"), "0x0040").unwrap();
+        fs::write(function_path.join("unknown/file"), "keep").unwrap();
+
+        assert!(remove_node(&function_path).is_err());
+        assert!(function_path.join("unknown/file").exists());
+    }
+
+    #[test]
+    fn gadgets_reports_each_gadget() {
+        let test = TestEnv::new("gadgets_reports_each_gadget");
+        keyboard::keyboard()
+            .create_config_in(&test.env, "keyboard")
+            .unwrap();
+        ns_procon::ns_procons()
+            .create_config_in(&test.env, "procons")
+            .unwrap();
+        let procons_path = test.env.gadget_path("procons").unwrap();
+        fs::write(procons_path.join("bDeviceClass"), "0x100").unwrap();
+
+        let gadgets = test.env.gadgets().unwrap();
+        assert_eq!(gadgets.len(), 2);
+        assert_eq!(gadgets[0].0, "keyboard");
+        assert_eq!(gadgets[0].1.as_ref().unwrap().gadget, keyboard::keyboard());
+        assert_eq!(gadgets[1].0, "procons");
+        assert!(matches!(gadgets[1].1, Err(GadgetError::Io { .. })));
+    }
+
+    #[test]
+    fn remove_config_removes_everything() {
+        let test = TestEnv::new("remove_config_removes_everything");
+        ns_procon::ns_procons()
+            .create_config_in(&test.env, "procons")
+            .unwrap();
+        keyboard::keyboard()
+            .create_config_in(&test.env, "keyboard")
+            .unwrap();
+
+        test.env.remove_config("procons").unwrap();
+        assert!(!test.env.gadget_path("procons").unwrap().exists());
+        // Other gadgets are left alone
+        assert_eq!(
+            test.env.load("keyboard").unwrap().gadget,
+            keyboard::keyboard()
+        );
+    }
+}
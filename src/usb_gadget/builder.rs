@@ -0,0 +1,98 @@
+use crate::usb_gadget::*;
+
+/// Builds a `Gadget` from scratch, checking it with `Gadget::validate`
+/// before handing it back
+///
+/// ```no_run
+/// use controller_emulator::usb_gadget::{keyboard, Config, Gadget};
+///
+/// let gadget = Gadget::builder(0x1d6b, 0x0104)
+///     .product("My keyboard")
+///     .hid_function(keyboard::keyboard_function())
+///     .config(Config::new("HID Configuration").hid_function(0))
+///     .build()
+///     .expect("invalid gadget");
+/// gadget.create_config("keyboard").unwrap();
+/// ```
+pub struct GadgetBuilder {
+    gadget: Gadget,
+}
+
+impl GadgetBuilder {
+    pub(super) fn new(vendor_id: u32, product_id: u32) -> GadgetBuilder {
+        GadgetBuilder {
+            gadget: Gadget {
+                device_max_packet_size: 64,
+                device_version: 0x100,
+                usb_version: 0x200,
+                vendor_id,
+                product_id,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn max_speed(mut self, max_speed: Speed) -> GadgetBuilder {
+        self.gadget.max_speed = max_speed;
+        self
+    }
+
+    /// bDeviceClass, bDeviceSubClass and bDeviceProtocol. These default to
+    /// 0, leaving the class to each interface.
+    pub fn device_class(mut self, class: u8, sub_class: u8, protocol: u8) -> GadgetBuilder {
+        self.gadget.device_class = class;
+        self.gadget.device_sub_class = sub_class;
+        self.gadget.device_protocol = protocol;
+        self
+    }
+
+    /// bMaxPacketSize0, one of 8, 16, 32 or 64
+    pub fn max_packet_size(mut self, size: u8) -> GadgetBuilder {
+        self.gadget.device_max_packet_size = size;
+        self
+    }
+
+    /// bcdDevice, e.g. 0x0210 for 2.10
+    pub fn device_version(mut self, version: u32) -> GadgetBuilder {
+        self.gadget.device_version = version;
+        self
+    }
+
+    /// bcdUSB, e.g. 0x0200 for USB 2.0
+    pub fn usb_version(mut self, version: u32) -> GadgetBuilder {
+        self.gadget.usb_version = version;
+        self
+    }
+
+    pub fn serial_number(mut self, serial_number: &str) -> GadgetBuilder {
+        self.gadget.serialnumber = serial_number.to_string();
+        self
+    }
+
+    pub fn product(mut self, product: &str) -> GadgetBuilder {
+        self.gadget.product = product.to_string();
+        self
+    }
+
+    pub fn manufacturer(mut self, manufacturer: &str) -> GadgetBuilder {
+        self.gadget.manufacturer = manufacturer.to_string();
+        self
+    }
+
+    /// Adds a HID function. Configs refer to functions by the order they
+    /// were added, starting at 0.
+    pub fn hid_function(mut self, function: HIDFunction) -> GadgetBuilder {
+        self.gadget.hid_functions.push(function);
+        self
+    }
+
+    pub fn config(mut self, config: Config) -> GadgetBuilder {
+        self.gadget.configs.push(config);
+        self
+    }
+
+    pub fn build(self) -> std::result::Result<Gadget, ValidationError> {
+        self.gadget.validate()?;
+        Ok(self.gadget)
+    }
+}
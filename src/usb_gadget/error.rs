@@ -0,0 +1,127 @@
+use crate::usb_gadget::ValidationError;
+use nix::errno::Errno;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors from setting up, binding or tearing down a gadget
+#[derive(Debug)]
+pub enum GadgetError {
+    /// The configfs `usb_gadget` directory doesn't exist. Is configfs
+    /// mounted and the libcomposite module loaded?
+    ConfigfsNotMounted(PathBuf),
+    PermissionDenied(PathBuf),
+    /// The UDC is already bound to another gadget
+    UdcBusy(String),
+    NoSuchUdc(String),
+    NoSuchGadget(String),
+    /// The gadget name is empty or isn't a single path component
+    InvalidName(String),
+    /// The gadget has no HID function at this index
+    NoSuchFunction(usize),
+    /// udev didn't create the device node of a HID function in time
+    NodeTimeout(PathBuf),
+    /// Every UDC is bound to some gadget or legacy gadget driver
+    NoFreeUdc,
+    /// The kernel refused to change or remove this path because the gadget
+    /// is bound to a UDC or a function is still linked into a config
+    GadgetInUse(PathBuf),
+    /// The kernel refused the value written to an attribute
+    AttributeRejected {
+        path: PathBuf,
+        value: String,
+    },
+    Invalid(ValidationError),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+impl GadgetError {
+    /// Classifies an error from reading or writing `path`
+    pub(super) fn io(path: &Path, source: io::Error) -> GadgetError {
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => GadgetError::PermissionDenied(path.to_path_buf()),
+            io::ErrorKind::ResourceBusy => GadgetError::GadgetInUse(path.to_path_buf()),
+            _ => GadgetError::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+        }
+    }
+
+    /// Classifies an error from writing `value` to an attribute
+    pub(super) fn attribute(path: &Path, value: &[u8], source: io::Error) -> GadgetError {
+        let rejected = source.kind() == io::ErrorKind::InvalidInput
+            || source.raw_os_error() == Some(Errno::ERANGE as i32);
+        if rejected {
+            return GadgetError::AttributeRejected {
+                path: path.to_path_buf(),
+                value: String::from_utf8_lossy(value).into_owned(),
+            };
+        }
+        GadgetError::io(path, source)
+    }
+
+    /// Classifies an error from binding to `udc`
+    pub(super) fn bind(path: &Path, udc: &str, source: io::Error) -> GadgetError {
+        match source.kind() {
+            io::ErrorKind::ResourceBusy => GadgetError::UdcBusy(udc.to_string()),
+            io::ErrorKind::NotFound => GadgetError::NoSuchUdc(udc.to_string()),
+            _ if source.raw_os_error() == Some(Errno::ENODEV as i32) => {
+                GadgetError::NoSuchUdc(udc.to_string())
+            }
+            _ => GadgetError::io(path, source),
+        }
+    }
+}
+
+impl fmt::Display for GadgetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GadgetError::ConfigfsNotMounted(path) => write!(
+                f,
+                "{} doesn't exist, is configfs mounted and libcomposite loaded?",
+                path.display()
+            ),
+            GadgetError::PermissionDenied(path) => {
+                write!(f, "permission denied for {}", path.display())
+            }
+            GadgetError::UdcBusy(udc) => write!(f, "UDC {} is in use by another gadget", udc),
+            GadgetError::NoSuchUdc(udc) => write!(f, "no UDC named {}", udc),
+            GadgetError::NoSuchGadget(name) => write!(f, "no gadget named {}", name),
+            GadgetError::InvalidName(name) => write!(f, "{:?} isn't a valid gadget name", name),
+            GadgetError::NoSuchFunction(index) => write!(f, "no HID function {}", index),
+            GadgetError::NodeTimeout(path) => {
+                write!(f, "timed out waiting for {} to be created", path.display())
+            }
+            GadgetError::NoFreeUdc => write!(f, "every UDC is in use"),
+            GadgetError::GadgetInUse(path) => {
+                write!(f, "{} is busy, is the gadget still bound?", path.display())
+            }
+            GadgetError::AttributeRejected { path, value } => {
+                write!(f, "kernel rejected {:?} for {}", value, path.display())
+            }
+            GadgetError::Invalid(error) => write!(f, "invalid gadget: {}", error),
+            GadgetError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for GadgetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GadgetError::Invalid(error) => Some(error),
+            GadgetError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ValidationError> for GadgetError {
+    fn from(error: ValidationError) -> Self {
+        GadgetError::Invalid(error)
+    }
+}
@@ -0,0 +1,62 @@
+use crate::usb_gadget::*;
+
+/// The real adapter's report descriptor. It declares the 0x21 input report
+/// as 37 bytes after its ID, one more than the adapter actually sends.
+pub fn report_desc() -> Vec<u8> {
+    vec![
+        0x05, 0x05, 0x09, 0x00, 0xA1, 0x01, 0x85, 0x11, 0x19, 0x00, 0x2A, 0xFF, 0x00, 0x15, 0x00,
+        0x26, 0xFF, 0x00, 0x75, 0x08, 0x95, 0x05, 0x91, 0x00, 0xC0, 0xA1, 0x01, 0x85, 0x21, 0x19,
+        0x00, 0x2A, 0xFF, 0x00, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08, 0x95, 0x25, 0x81, 0x00,
+        0xC0, 0xA1, 0x01, 0x85, 0x12, 0x19, 0x00, 0x2A, 0xFF, 0x00, 0x15, 0x00, 0x26, 0xFF, 0x00,
+        0x75, 0x08, 0x95, 0x01, 0x91, 0x00, 0xC0, 0xA1, 0x01, 0x85, 0x22, 0x19, 0x00, 0x2A, 0xFF,
+        0x00, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08, 0x95, 0x19, 0x81, 0x00, 0xC0, 0xA1, 0x01,
+        0x85, 0x13, 0x19, 0x00, 0x2A, 0xFF, 0x00, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08, 0x95,
+        0x01, 0x91, 0x00, 0xC0, 0xA1, 0x01, 0x85, 0x23, 0x19, 0x00, 0x2A, 0xFF, 0x00, 0x15, 0x00,
+        0x26, 0xFF, 0x00, 0x75, 0x08, 0x95, 0x02, 0x81, 0x00, 0xC0, 0xA1, 0x01, 0x85, 0x14, 0x19,
+        0x00, 0x2A, 0xFF, 0x00, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08, 0x95, 0x01, 0x91, 0x00,
+        0xC0, 0xA1, 0x01, 0x85, 0x24, 0x19, 0x00, 0x2A, 0xFF, 0x00, 0x15, 0x00, 0x26, 0xFF, 0x00,
+        0x75, 0x08, 0x95, 0x02, 0x81, 0x00, 0xC0, 0xA1, 0x01, 0x85, 0x15, 0x19, 0x00, 0x2A, 0xFF,
+        0x00, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08, 0x95, 0x01, 0x91, 0x00, 0xC0, 0xA1, 0x01,
+        0x85, 0x25, 0x19, 0x00, 0x2A, 0xFF, 0x00, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08, 0x95,
+        0x02, 0x81, 0x00, 0xC0,
+    ]
+}
+
+/// The official GameCube controller adapter (WUP-028)
+pub fn gc_adapter() -> Gadget {
+    let config = Config {
+        attributes: 0xE0,
+        max_power: 500,
+        description: "HID Configuration".to_string(),
+
+        hid_functions: vec![0],
+    };
+
+    let func = HIDFunction {
+        report_desc: report_desc(),
+        // The real adapter's endpoint is 37 bytes, which is all the emulator
+        // writes. f_hid doesn't parse the descriptor, but validation checks
+        // the report length against it, and the 0x21 report it declares is
+        // 38 bytes.
+        report_length: 38,
+        ..Default::default()
+    };
+
+    Gadget {
+        device_max_packet_size: 64,
+
+        device_version: 0x100,
+        usb_version: 0x200,
+        product_id: 0x0337,
+        vendor_id: 0x057E,
+
+        serialnumber: "15/07/2014".to_string(),
+        product: "WUP-028".to_string(),
+        manufacturer: "Nintendo".to_string(),
+
+        configs: vec![config],
+        hid_functions: vec![func],
+
+        ..Default::default()
+    }
+}
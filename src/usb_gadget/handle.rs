@@ -0,0 +1,140 @@
+use crate::usb_gadget::{Gadget, GadgetEnv, Result};
+
+/// A gadget written to configfs and bound to a UDC. Dropping the handle, or
+/// calling `close`, unbinds the gadget and removes it, so it's cleaned up
+/// when the owner returns early or unwinds from a panic.
+#[derive(Debug)]
+pub struct GadgetHandle {
+    env: GadgetEnv,
+    name: String,
+    udc: String,
+    gadget: Gadget,
+    closed: bool,
+}
+
+impl GadgetHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The UDC the gadget is bound to
+    pub fn udc(&self) -> &str {
+        &self.udc
+    }
+
+    pub fn gadget(&self) -> &Gadget {
+        &self.gadget
+    }
+
+    pub fn env(&self) -> &GadgetEnv {
+        &self.env
+    }
+
+    /// Unbinds and removes the gadget, reporting any error that dropping
+    /// the handle would only log. If removing fails, dropping the handle
+    /// tries again.
+    pub fn close(mut self) -> Result<()> {
+        self.remove()
+    }
+
+    /// Removes the gadget unless that's already been done
+    fn remove(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.env.remove_config(&self.name)?;
+        self.closed = true;
+        Ok(())
+    }
+}
+
+impl Drop for GadgetHandle {
+    fn drop(&mut self) {
+        if let Err(e) = self.remove() {
+            log::error!("couldn't remove gadget {}: {}", self.name, e);
+        }
+    }
+}
+
+impl GadgetEnv {
+    /// Writes the gadget to <configfs>/<name>, replacing any gadget there,
+    /// and binds it to the first free UDC
+    pub fn instantiate(&self, gadget: &Gadget, name: &str) -> Result<GadgetHandle> {
+        gadget.create_config_in(self, name)?;
+        let mut handle = GadgetHandle {
+            env: self.clone(),
+            name: name.to_string(),
+            udc: String::new(),
+            gadget: gadget.clone(),
+            closed: false,
+        };
+        // The handle already owns the configfs tree, so it's removed again
+        // if binding fails
+        handle.udc = self.bind_any(name)?;
+        Ok(handle)
+    }
+}
+
+impl Gadget {
+    /// Writes the gadget to /sys/kernel/config/usb_gadget/<name> and binds
+    /// it to the first free UDC. See `GadgetEnv::instantiate`.
+    pub fn instantiate(&self, name: &str) -> Result<GadgetHandle> {
+        GadgetEnv::default().instantiate(self, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::usb_gadget::keyboard::keyboard;
+    use crate::usb_gadget::tests::TestEnv;
+    use std::fs;
+
+    /// A test environment whose UDC is free to bind
+    fn test_env(test: &str) -> TestEnv {
+        let test = TestEnv::new(test);
+        fs::write(test.env.udc_class().join("dummy_udc.0/function"), "").unwrap();
+        test
+    }
+
+    #[test]
+    fn dropping_removes_the_gadget() {
+        let test = test_env("dropping_removes_the_gadget");
+        let handle = test.env.instantiate(&keyboard(), "keyboard").unwrap();
+        assert_eq!(handle.udc(), "dummy_udc.0");
+        let base_path = test.env.gadget_path("keyboard").unwrap();
+        assert!(base_path.exists());
+
+        drop(handle);
+        assert!(!base_path.exists());
+    }
+
+    #[test]
+    fn closing_removes_the_gadget_once() {
+        let test = test_env("closing_removes_the_gadget_once");
+        let mut handle = test.env.instantiate(&keyboard(), "keyboard").unwrap();
+        let base_path = test.env.gadget_path("keyboard").unwrap();
+        handle.remove().unwrap();
+        assert!(!base_path.exists());
+
+        // Something else now owns the name, so neither closing nor the
+        // drop that follows touches it
+        keyboard().create_config_in(&test.env, "keyboard").unwrap();
+        handle.close().unwrap();
+        assert!(base_path.exists());
+    }
+
+    #[test]
+    fn failed_close_is_retried_on_drop() {
+        let test = test_env("failed_close_is_retried_on_drop");
+        let mut handle = test.env.instantiate(&keyboard(), "keyboard").unwrap();
+        let base_path = test.env.gadget_path("keyboard").unwrap();
+        // A directory remove_config won't remove
+        let unknown = base_path.join("functions/hid.usb.0/unknown");
+        fs::create_dir(&unknown).unwrap();
+        assert!(handle.remove().is_err());
+
+        fs::remove_dir(&unknown).unwrap();
+        drop(handle);
+        assert!(!base_path.exists());
+    }
+}
@@ -0,0 +1,767 @@
+use std::error::Error;
+use std::fmt;
+
+mod builder;
+mod print;
+
+pub use builder::{Builder, Collection};
+pub use print::pretty_print;
+
+pub mod usage_page {
+    pub const GENERIC_DESKTOP: u16 = 0x01;
+    pub const SIMULATION: u16 = 0x02;
+    pub const KEYBOARD: u16 = 0x07;
+    pub const LED: u16 = 0x08;
+    pub const BUTTON: u16 = 0x09;
+    pub const CONSUMER: u16 = 0x0c;
+}
+
+pub mod generic_desktop {
+    pub const POINTER: u16 = 0x01;
+    pub const MOUSE: u16 = 0x02;
+    pub const JOYSTICK: u16 = 0x04;
+    pub const GAMEPAD: u16 = 0x05;
+    pub const KEYBOARD: u16 = 0x06;
+    pub const X: u16 = 0x30;
+    pub const Y: u16 = 0x31;
+    pub const Z: u16 = 0x32;
+    pub const RX: u16 = 0x33;
+    pub const RY: u16 = 0x34;
+    pub const RZ: u16 = 0x35;
+    pub const SLIDER: u16 = 0x36;
+    pub const DIAL: u16 = 0x37;
+    pub const WHEEL: u16 = 0x38;
+    pub const HAT_SWITCH: u16 = 0x39;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Usage {
+    pub page: u16,
+    pub id: u16,
+}
+
+impl Usage {
+    /// Builds a usage from a local usage item, where 4 byte items carry
+    /// their own page in the upper half
+    fn from_item(page: u16, item: &Item) -> Usage {
+        if item.size == 4 {
+            Usage {
+                page: (item.data >> 16) as u16,
+                id: item.data as u16,
+            }
+        } else {
+            Usage {
+                page,
+                id: item.data as u16,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use generic_desktop::*;
+
+        let name = match (self.page, self.id) {
+            (usage_page::GENERIC_DESKTOP, POINTER) => "Pointer",
+            (usage_page::GENERIC_DESKTOP, MOUSE) => "Mouse",
+            (usage_page::GENERIC_DESKTOP, JOYSTICK) => "Joystick",
+            (usage_page::GENERIC_DESKTOP, GAMEPAD) => "Gamepad",
+            (usage_page::GENERIC_DESKTOP, KEYBOARD) => "Keyboard",
+            (usage_page::GENERIC_DESKTOP, X) => "X",
+            (usage_page::GENERIC_DESKTOP, Y) => "Y",
+            (usage_page::GENERIC_DESKTOP, Z) => "Z",
+            (usage_page::GENERIC_DESKTOP, RX) => "Rx",
+            (usage_page::GENERIC_DESKTOP, RY) => "Ry",
+            (usage_page::GENERIC_DESKTOP, RZ) => "Rz",
+            (usage_page::GENERIC_DESKTOP, SLIDER) => "Slider",
+            (usage_page::GENERIC_DESKTOP, DIAL) => "Dial",
+            (usage_page::GENERIC_DESKTOP, WHEEL) => "Wheel",
+            (usage_page::GENERIC_DESKTOP, HAT_SWITCH) => "Hat switch",
+            (usage_page::BUTTON, id) => return write!(f, "Button {}", id),
+            (page, id) => return write!(f, "{:#06x}:{:#06x}", page, id),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Flag bits of input, output and feature items
+pub mod flags {
+    pub const DATA: u32 = 0x00;
+    pub const CONSTANT: u32 = 0x01;
+    pub const ARRAY: u32 = 0x00;
+    pub const VARIABLE: u32 = 0x02;
+    pub const ABSOLUTE: u32 = 0x00;
+    pub const RELATIVE: u32 = 0x04;
+    pub const WRAP: u32 = 0x08;
+    pub const NON_LINEAR: u32 = 0x10;
+    pub const NO_PREFERRED: u32 = 0x20;
+    pub const NULL_STATE: u32 = 0x40;
+    pub const VOLATILE: u32 = 0x80;
+    pub const BUFFERED_BYTES: u32 = 0x100;
+}
+
+/// Problems found while parsing or validating a report descriptor
+#[derive(Debug, Clone, PartialEq)]
+pub enum DescriptorError {
+    /// An item at this byte offset runs past the end of the descriptor
+    Truncated(usize),
+    EndWithoutCollection,
+    /// Collections still open at the end of the descriptor
+    UnclosedCollections(usize),
+    PopWithoutPush,
+    InvalidUsageRange,
+    ReportIdOutOfRange(u32),
+    /// A report's size in bits doesn't fit in a `usize`
+    ReportTooLarge {
+        kind: ReportKind,
+        report_id: u8,
+    },
+    /// Some reports have IDs and others don't, so the host can't tell them
+    /// apart
+    MixedReportIds,
+    /// A report, with its ID byte, won't fit in the function's report length
+    ReportTooLong {
+        kind: ReportKind,
+        report_id: u8,
+        len: usize,
+        report_length: usize,
+    },
+}
+
+impl fmt::Display for DescriptorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DescriptorError::Truncated(offset) => {
+                write!(f, "item at byte {} is truncated", offset)
+            }
+            DescriptorError::EndWithoutCollection => {
+                write!(f, "end collection without a matching collection")
+            }
+            DescriptorError::UnclosedCollections(depth) => {
+                write!(f, "{} collection(s) left open", depth)
+            }
+            DescriptorError::PopWithoutPush => write!(f, "pop without a matching push"),
+            DescriptorError::InvalidUsageRange => write!(f, "invalid usage range"),
+            DescriptorError::ReportIdOutOfRange(id) => {
+                write!(f, "report ID {} is out of range", id)
+            }
+            DescriptorError::ReportTooLarge { kind, report_id } => {
+                write!(f, "{:?} report {} is too large", kind, report_id)
+            }
+            DescriptorError::MixedReportIds => {
+                write!(f, "some reports have report IDs and some don't")
+            }
+            DescriptorError::ReportTooLong {
+                kind,
+                report_id,
+                len,
+                report_length,
+            } => write!(
+                f,
+                "{:?} report {} is {} bytes but the report length is {}",
+                kind, report_id, len, report_length
+            ),
+        }
+    }
+}
+
+impl Error for DescriptorError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemType {
+    Main,
+    Global,
+    Local,
+    Reserved,
+}
+
+/// A single short item of a report descriptor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Item {
+    pub item_type: ItemType,
+    pub tag: u8,
+    /// Number of data bytes: 0, 1, 2 or 4
+    pub size: u8,
+    /// The data bytes, little endian and zero extended
+    pub data: u32,
+}
+
+impl Item {
+    /// An item holding an unsigned value, in as few bytes as fit it
+    pub fn unsigned(item_type: ItemType, tag: u8, data: u32) -> Item {
+        let size = match data {
+            0..=0xff => 1,
+            0x100..=0xffff => 2,
+            _ => 4,
+        };
+        Item {
+            item_type,
+            tag,
+            size,
+            data,
+        }
+    }
+
+    /// An item holding a signed value, in as few bytes as fit it
+    pub fn signed(item_type: ItemType, tag: u8, data: i32) -> Item {
+        let size = match data {
+            -0x80..=0x7f => 1,
+            -0x8000..=0x7fff => 2,
+            _ => 4,
+        };
+        Item {
+            item_type,
+            tag,
+            size,
+            data: data as u32 & (u64::MAX >> (64 - 8 * size as u64)) as u32,
+        }
+    }
+
+    /// An item without data, like end collection
+    pub fn empty(item_type: ItemType, tag: u8) -> Item {
+        Item {
+            item_type,
+            tag,
+            size: 0,
+            data: 0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let size_code = match self.size {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            _ => 3,
+        };
+        let type_code = match self.item_type {
+            ItemType::Main => 0,
+            ItemType::Global => 1,
+            ItemType::Local => 2,
+            ItemType::Reserved => 3,
+        };
+        let mut bytes = vec![self.tag << 4 | type_code << 2 | size_code];
+        bytes.extend_from_slice(&self.data.to_le_bytes()[..self.size as usize]);
+        bytes
+    }
+
+    /// The data sign extended from its size, as used by logical and
+    /// physical extents
+    pub fn signed_data(&self) -> i32 {
+        match self.size {
+            1 => self.data as i8 as i32,
+            2 => self.data as i16 as i32,
+            _ => self.data as i32,
+        }
+    }
+}
+
+/// Splits a report descriptor into its items. Long items, which no
+/// standard usage defines, are skipped.
+pub fn items(desc: &[u8]) -> Result<Vec<Item>, DescriptorError> {
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < desc.len() {
+        let prefix = desc[i];
+        if prefix == 0xfe {
+            let len = match desc.get(i + 1) {
+                Some(len) => *len as usize,
+                None => return Err(DescriptorError::Truncated(i)),
+            };
+            if i + 3 + len > desc.len() {
+                return Err(DescriptorError::Truncated(i));
+            }
+            i += 3 + len;
+            continue;
+        }
+
+        let size = match prefix & 0x3 {
+            3 => 4,
+            size => size,
+        };
+        let data = match desc.get(i + 1..i + 1 + size as usize) {
+            Some(data) => data
+                .iter()
+                .rev()
+                .fold(0, |acc, byte| acc << 8 | *byte as u32),
+            None => return Err(DescriptorError::Truncated(i)),
+        };
+        let item_type = match (prefix >> 2) & 0x3 {
+            0 => ItemType::Main,
+            1 => ItemType::Global,
+            2 => ItemType::Local,
+            _ => ItemType::Reserved,
+        };
+        items.push(Item {
+            item_type,
+            tag: prefix >> 4,
+            size,
+            data,
+        });
+        i += 1 + size as usize;
+    }
+    Ok(items)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportKind {
+    Input,
+    Output,
+    Feature,
+}
+
+/// A run of identically sized elements in a report, from a single input,
+/// output or feature item
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub kind: ReportKind,
+    /// 0 when the descriptor doesn't use report IDs
+    pub report_id: u8,
+    /// Offset in bits from the start of the report, after the report ID
+    pub bit_offset: usize,
+    /// Size of each element in bits
+    pub size: usize,
+    pub count: usize,
+    /// The main item's data bits (constant, variable, relative, ...)
+    pub flags: u32,
+    pub usages: Vec<Usage>,
+    pub logical_min: i32,
+    pub logical_max: i32,
+}
+
+impl Field {
+    pub fn is_constant(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// Whether each element is its own control, rather than an array of
+    /// usage indices
+    pub fn is_variable(&self) -> bool {
+        self.flags & 0x02 != 0
+    }
+
+    pub fn is_relative(&self) -> bool {
+        self.flags & 0x04 != 0
+    }
+
+    pub fn has_null_state(&self) -> bool {
+        self.flags & 0x40 != 0
+    }
+
+    /// The usage of element `i`. When there are fewer usages than elements
+    /// the last usage applies to the rest.
+    pub fn usage(&self, i: usize) -> Option<Usage> {
+        self.usages.get(i).or_else(|| self.usages.last()).copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct GlobalState {
+    usage_page: u16,
+    logical_min: i32,
+    logical_max: i32,
+    /// The raw logical maximum item, reinterpreted once the minimum is known
+    logical_max_item: Option<Item>,
+    report_size: usize,
+    report_id: u8,
+    report_count: usize,
+}
+
+impl GlobalState {
+    /// Many descriptors give an unsigned maximum like 0xff in a single byte,
+    /// which read as signed would be below the minimum
+    fn logical_max(&self) -> i32 {
+        match self.logical_max_item {
+            Some(item) if self.logical_max < self.logical_min => item.data as i32,
+            _ => self.logical_max,
+        }
+    }
+}
+
+/// The reports a HID report descriptor defines, as the fields that make
+/// them up
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportDescriptor {
+    pub fields: Vec<Field>,
+}
+
+impl ReportDescriptor {
+    pub fn parse(desc: &[u8]) -> Result<ReportDescriptor, DescriptorError> {
+        let mut fields: Vec<Field> = Vec::new();
+        let mut global = GlobalState::default();
+        let mut global_stack = Vec::new();
+        let mut usages = Vec::new();
+        let mut usage_min = None;
+        let mut depth = 0;
+
+        for item in items(desc)? {
+            match (item.item_type, item.tag) {
+                // Input, output and feature
+                (ItemType::Main, 0x8) | (ItemType::Main, 0x9) | (ItemType::Main, 0xb) => {
+                    let kind = match item.tag {
+                        0x8 => ReportKind::Input,
+                        0x9 => ReportKind::Output,
+                        _ => ReportKind::Feature,
+                    };
+                    // The end of the report so far, which was checked when
+                    // its last field was added
+                    let bit_offset = fields
+                        .iter()
+                        .rev()
+                        .find(|f| f.kind == kind && f.report_id == global.report_id)
+                        .map_or(0, |f| f.bit_offset + f.size * f.count);
+                    global
+                        .report_size
+                        .checked_mul(global.report_count)
+                        .and_then(|bits| bit_offset.checked_add(bits))
+                        .ok_or(DescriptorError::ReportTooLarge {
+                            kind,
+                            report_id: global.report_id,
+                        })?;
+                    fields.push(Field {
+                        kind,
+                        report_id: global.report_id,
+                        bit_offset,
+                        size: global.report_size,
+                        count: global.report_count,
+                        flags: item.data,
+                        usages: usages.split_off(0),
+                        logical_min: global.logical_min,
+                        logical_max: global.logical_max(),
+                    });
+                    usage_min = None;
+                }
+                // Collection
+                (ItemType::Main, 0xa) => {
+                    depth += 1;
+                    usages.clear();
+                    usage_min = None;
+                }
+                // End collection
+                (ItemType::Main, 0xc) => {
+                    if depth == 0 {
+                        return Err(DescriptorError::EndWithoutCollection);
+                    }
+                    depth -= 1;
+                }
+                (ItemType::Global, 0x0) => global.usage_page = item.data as u16,
+                (ItemType::Global, 0x1) => global.logical_min = item.signed_data(),
+                (ItemType::Global, 0x2) => {
+                    global.logical_max = item.signed_data();
+                    global.logical_max_item = Some(item);
+                }
+                (ItemType::Global, 0x7) => global.report_size = item.data as usize,
+                (ItemType::Global, 0x8) => {
+                    if item.data == 0 || item.data > 0xff {
+                        return Err(DescriptorError::ReportIdOutOfRange(item.data));
+                    }
+                    global.report_id = item.data as u8;
+                }
+                (ItemType::Global, 0x9) => global.report_count = item.data as usize,
+                // Push and pop
+                (ItemType::Global, 0xa) => global_stack.push(global),
+                (ItemType::Global, 0xb) => match global_stack.pop() {
+                    Some(pushed) => global = pushed,
+                    None => return Err(DescriptorError::PopWithoutPush),
+                },
+                (ItemType::Local, 0x0) => usages.push(Usage::from_item(global.usage_page, &item)),
+                (ItemType::Local, 0x1) => {
+                    usage_min = Some(Usage::from_item(global.usage_page, &item))
+                }
+                (ItemType::Local, 0x2) => {
+                    let max = Usage::from_item(global.usage_page, &item);
+                    match usage_min.take() {
+                        Some(min) if min.page == max.page && min.id <= max.id => {
+                            usages.extend((min.id..=max.id).map(|id| Usage { page: min.page, id }))
+                        }
+                        _ => return Err(DescriptorError::InvalidUsageRange),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if depth != 0 {
+            return Err(DescriptorError::UnclosedCollections(depth));
+        }
+        Ok(ReportDescriptor { fields })
+    }
+
+    /// The fields of one report, in order
+    pub fn report(&self, kind: ReportKind, report_id: u8) -> impl Iterator<Item = &Field> {
+        self.fields
+            .iter()
+            .filter(move |f| f.kind == kind && f.report_id == report_id)
+    }
+
+    /// The IDs of all reports of a kind, in the order they're first defined
+    pub fn report_ids(&self, kind: ReportKind) -> Vec<u8> {
+        let mut ids = Vec::new();
+        for field in self.fields.iter().filter(|f| f.kind == kind) {
+            if !ids.contains(&field.report_id) {
+                ids.push(field.report_id);
+            }
+        }
+        ids
+    }
+
+    /// Length of a report in bytes, not counting the report ID. Parsing
+    /// rejects reports too large to count, but fields can also be built by
+    /// hand, so this saturates rather than overflowing.
+    pub fn report_len(&self, kind: ReportKind, report_id: u8) -> usize {
+        let bits = self
+            .report(kind, report_id)
+            .map(|f| f.size.saturating_mul(f.count))
+            .fold(0, usize::saturating_add);
+        bits.div_ceil(8)
+    }
+}
+
+/// Checks a descriptor for a HID function with the given report length,
+/// returning every problem found. Reports longer than the report length
+/// are flagged, but shorter ones aren't.
+pub fn validate(desc: &[u8], report_length: usize) -> Result<(), Vec<DescriptorError>> {
+    let parsed = ReportDescriptor::parse(desc).map_err(|e| vec![e])?;
+    let mut errors = Vec::new();
+
+    let uses_ids = parsed.fields.iter().any(|f| f.report_id != 0);
+    if uses_ids && parsed.fields.iter().any(|f| f.report_id == 0) {
+        errors.push(DescriptorError::MixedReportIds);
+    }
+
+    // Feature reports go over the control endpoint, so only input and
+    // output reports are limited by the report length. Shorter reports are
+    // fine: report_length is the endpoints' max packet size and f_hid sends
+    // each write as a packet of its own length, so any descriptor with
+    // reports of different sizes has some shorter than report_length.
+    for kind in [ReportKind::Input, ReportKind::Output] {
+        for report_id in parsed.report_ids(kind) {
+            let len = parsed.report_len(kind, report_id) + if uses_ids { 1 } else { 0 };
+            if len > report_length {
+                errors.push(DescriptorError::ReportTooLong {
+                    kind,
+                    report_id,
+                    len,
+                    report_length,
+                });
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One byte buttons in an application collection
+    fn buttons() -> Builder {
+        Builder::new()
+            .usage_page(usage_page::GENERIC_DESKTOP)
+            .usage(generic_desktop::GAMEPAD)
+            .collection(Collection::Application)
+            .usage_page(usage_page::BUTTON)
+            .usage_range(1, 8)
+            .logical_range(0, 1)
+            .report_size(1)
+            .report_count(8)
+            .input(flags::DATA | flags::VARIABLE)
+    }
+
+    #[test]
+    fn items_are_split() {
+        let desc = [
+            0x05, 0x01, 0x26, 0xff, 0x00, 0x27, 0x01, 0x02, 0x03, 0x04, 0xc0,
+        ];
+        assert_eq!(
+            items(&desc).unwrap(),
+            [
+                Item::unsigned(ItemType::Global, 0x0, 0x01),
+                Item {
+                    item_type: ItemType::Global,
+                    tag: 0x2,
+                    size: 2,
+                    data: 0xff,
+                },
+                Item {
+                    item_type: ItemType::Global,
+                    tag: 0x2,
+                    size: 4,
+                    data: 0x04030201,
+                },
+                Item::empty(ItemType::Main, 0xc),
+            ]
+        );
+        assert_eq!(
+            Item::signed(ItemType::Global, 0x1, -1).to_bytes(),
+            [0x15, 0xff]
+        );
+        assert_eq!(
+            Item::signed(ItemType::Global, 0x1, -129).signed_data(),
+            -129
+        );
+        assert_eq!(
+            Item::signed(ItemType::Global, 0x1, -129).to_bytes(),
+            [0x16, 0x7f, 0xff]
+        );
+    }
+
+    #[test]
+    fn malformed_items() {
+        // A two byte item with one byte left
+        assert_eq!(
+            items(&[0x05, 0x01, 0x26, 0xff]),
+            Err(DescriptorError::Truncated(2))
+        );
+        assert_eq!(
+            items(&[0x05, 0x01, 0xfe]),
+            Err(DescriptorError::Truncated(2))
+        );
+        // A long item claiming more data than there is
+        assert_eq!(
+            items(&[0xfe, 0x04, 0x10, 0x01, 0x02]),
+            Err(DescriptorError::Truncated(0))
+        );
+    }
+
+    #[test]
+    fn long_items_are_skipped() {
+        let desc = [0xfe, 0x02, 0x10, 0xaa, 0xbb, 0xc0];
+        assert_eq!(items(&desc).unwrap(), [Item::empty(ItemType::Main, 0xc)]);
+    }
+
+    #[test]
+    fn unbalanced_collections() {
+        let desc = buttons().build();
+        assert_eq!(
+            ReportDescriptor::parse(&desc),
+            Err(DescriptorError::UnclosedCollections(1))
+        );
+        let desc = buttons().end_collection().end_collection().build();
+        assert_eq!(
+            ReportDescriptor::parse(&desc),
+            Err(DescriptorError::EndWithoutCollection)
+        );
+    }
+
+    #[test]
+    fn malformed_globals_and_locals() {
+        let parse = |builder: Builder| ReportDescriptor::parse(&builder.build());
+        assert_eq!(
+            parse(Builder::new().pop()),
+            Err(DescriptorError::PopWithoutPush)
+        );
+        assert_eq!(
+            parse(Builder::new().usage_range(8, 1)),
+            Err(DescriptorError::InvalidUsageRange)
+        );
+        assert_eq!(
+            parse(Builder::new().report_id(0)),
+            Err(DescriptorError::ReportIdOutOfRange(0))
+        );
+        assert_eq!(
+            parse(Builder::new().item(Item::unsigned(ItemType::Global, 0x8, 0x100))),
+            Err(DescriptorError::ReportIdOutOfRange(0x100))
+        );
+    }
+
+    #[test]
+    fn oversized_reports() {
+        let huge = Builder::new()
+            .report_size(u32::MAX)
+            .report_count(u32::MAX)
+            .input(flags::CONSTANT)
+            .input(flags::CONSTANT)
+            .build();
+        assert_eq!(
+            ReportDescriptor::parse(&huge),
+            Err(DescriptorError::ReportTooLarge {
+                kind: ReportKind::Input,
+                report_id: 0
+            })
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let desc = buttons()
+            .usage_page(usage_page::GENERIC_DESKTOP)
+            .usage(generic_desktop::X)
+            .usage(generic_desktop::Y)
+            .logical_range(0, 255)
+            .report_size(8)
+            .report_count(2)
+            .input(flags::DATA | flags::VARIABLE)
+            .report_count(1)
+            .output(flags::DATA | flags::VARIABLE)
+            .end_collection()
+            .build();
+        let parsed = ReportDescriptor::parse(&desc).unwrap();
+        assert_eq!(parsed.fields.len(), 3);
+
+        let sticks = &parsed.fields[1];
+        assert_eq!(sticks.bit_offset, 8);
+        assert_eq!((sticks.size, sticks.count), (8, 2));
+        // 255 doesn't fit in a signed byte, so it's read as unsigned
+        assert_eq!((sticks.logical_min, sticks.logical_max), (0, 255));
+        assert_eq!(sticks.usage(1).unwrap().to_string(), "Y".to_string());
+        assert_eq!(parsed.fields[0].usage(7).unwrap().to_string(), "Button 8");
+
+        assert_eq!(parsed.report_ids(ReportKind::Input), [0]);
+        assert_eq!(parsed.report_len(ReportKind::Input, 0), 3);
+        assert_eq!(parsed.report_len(ReportKind::Output, 0), 1);
+        assert_eq!(parsed.report_len(ReportKind::Feature, 0), 0);
+    }
+
+    #[test]
+    fn validates_report_lengths() {
+        let desc = Builder::new()
+            .report_id(1)
+            .usage_page(usage_page::BUTTON)
+            .usage_range(1, 8)
+            .logical_range(0, 1)
+            .report_size(1)
+            .report_count(8)
+            .input(flags::DATA | flags::VARIABLE)
+            .report_size(8)
+            .report_count(4)
+            .output(flags::DATA | flags::VARIABLE)
+            .report_count(63)
+            .feature(flags::DATA | flags::VARIABLE)
+            .build();
+        // Feature reports aren't limited by the report length
+        assert_eq!(validate(&desc, 5), Ok(()));
+        assert_eq!(
+            validate(&desc, 4),
+            Err(vec![DescriptorError::ReportTooLong {
+                kind: ReportKind::Output,
+                report_id: 1,
+                len: 5,
+                report_length: 4,
+            }])
+        );
+    }
+
+    #[test]
+    fn validates_report_ids() {
+        let desc = buttons()
+            .report_id(2)
+            .input(flags::DATA | flags::VARIABLE)
+            .end_collection()
+            .build();
+        assert_eq!(
+            validate(&desc, 64),
+            Err(vec![DescriptorError::MixedReportIds])
+        );
+        assert_eq!(
+            validate(&[0xc0], 64),
+            Err(vec![DescriptorError::EndWithoutCollection])
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use super::{Item, ItemType, Usage};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Collection {
+    Physical,
+    Application,
+    Logical,
+    Report,
+    NamedArray,
+    UsageSwitch,
+    UsageModifier,
+    Vendor(u8),
+}
+
+impl Collection {
+    pub(super) fn from_id(id: u8) -> Collection {
+        match id {
+            0x00 => Collection::Physical,
+            0x01 => Collection::Application,
+            0x02 => Collection::Logical,
+            0x03 => Collection::Report,
+            0x04 => Collection::NamedArray,
+            0x05 => Collection::UsageSwitch,
+            0x06 => Collection::UsageModifier,
+            id => Collection::Vendor(id),
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            Collection::Physical => 0x00,
+            Collection::Application => 0x01,
+            Collection::Logical => 0x02,
+            Collection::Report => 0x03,
+            Collection::NamedArray => 0x04,
+            Collection::UsageSwitch => 0x05,
+            Collection::UsageModifier => 0x06,
+            Collection::Vendor(id) => id,
+        }
+    }
+}
+
+/// Builds a report descriptor item by item, e.g.
+///
+/// ```
+/// use controller_emulator::usb_gadget::hid::{flags, generic_desktop, usage_page};
+/// use controller_emulator::usb_gadget::hid::{Builder, Collection};
+///
+/// let desc = Builder::new()
+///     .usage_page(usage_page::GENERIC_DESKTOP)
+///     .usage(generic_desktop::GAMEPAD)
+///     .collection(Collection::Application)
+///     .usage_page(usage_page::BUTTON)
+///     .usage_range(1, 8)
+///     .logical_range(0, 1)
+///     .report_size(1)
+///     .report_count(8)
+///     .input(flags::DATA | flags::VARIABLE | flags::ABSOLUTE)
+///     .end_collection()
+///     .build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    items: Vec<Item>,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// Appends an arbitrary item
+    pub fn item(mut self, item: Item) -> Builder {
+        self.items.push(item);
+        self
+    }
+
+    fn global(self, tag: u8, data: u32) -> Builder {
+        self.item(Item::unsigned(ItemType::Global, tag, data))
+    }
+
+    fn local(self, tag: u8, data: u32) -> Builder {
+        self.item(Item::unsigned(ItemType::Local, tag, data))
+    }
+
+    pub fn usage_page(self, page: u16) -> Builder {
+        self.global(0x0, page as u32)
+    }
+
+    pub fn logical_range(self, min: i32, max: i32) -> Builder {
+        self.item(Item::signed(ItemType::Global, 0x1, min))
+            .item(Item::signed(ItemType::Global, 0x2, max))
+    }
+
+    pub fn physical_range(self, min: i32, max: i32) -> Builder {
+        self.item(Item::signed(ItemType::Global, 0x3, min))
+            .item(Item::signed(ItemType::Global, 0x4, max))
+    }
+
+    pub fn unit_exponent(self, exponent: i8) -> Builder {
+        self.global(0x5, exponent as u8 as u32 & 0xf)
+    }
+
+    pub fn unit(self, unit: u32) -> Builder {
+        self.global(0x6, unit)
+    }
+
+    /// Size of each element in bits
+    pub fn report_size(self, bits: u32) -> Builder {
+        self.global(0x7, bits)
+    }
+
+    pub fn report_id(self, id: u8) -> Builder {
+        self.global(0x8, id as u32)
+    }
+
+    pub fn report_count(self, count: u32) -> Builder {
+        self.global(0x9, count)
+    }
+
+    pub fn push(self) -> Builder {
+        self.item(Item::empty(ItemType::Global, 0xa))
+    }
+
+    pub fn pop(self) -> Builder {
+        self.item(Item::empty(ItemType::Global, 0xb))
+    }
+
+    /// A usage on the current usage page
+    pub fn usage(self, id: u16) -> Builder {
+        self.local(0x0, id as u32)
+    }
+
+    /// A usage with its own page, encoded as a 4 byte item
+    pub fn extended_usage(self, usage: Usage) -> Builder {
+        self.item(Item {
+            item_type: ItemType::Local,
+            tag: 0x0,
+            size: 4,
+            data: (usage.page as u32) << 16 | usage.id as u32,
+        })
+    }
+
+    /// Usages `min` to `max` inclusive on the current usage page
+    pub fn usage_range(self, min: u16, max: u16) -> Builder {
+        self.local(0x1, min as u32).local(0x2, max as u32)
+    }
+
+    /// Input item, with bits from `flags`
+    pub fn input(self, flags: u32) -> Builder {
+        self.item(Item::unsigned(ItemType::Main, 0x8, flags))
+    }
+
+    /// Output item, with bits from `flags`
+    pub fn output(self, flags: u32) -> Builder {
+        self.item(Item::unsigned(ItemType::Main, 0x9, flags))
+    }
+
+    /// Feature item, with bits from `flags`
+    pub fn feature(self, flags: u32) -> Builder {
+        self.item(Item::unsigned(ItemType::Main, 0xb, flags))
+    }
+
+    pub fn collection(self, collection: Collection) -> Builder {
+        self.item(Item::unsigned(ItemType::Main, 0xa, collection.id() as u32))
+    }
+
+    pub fn end_collection(self) -> Builder {
+        self.item(Item::empty(ItemType::Main, 0xc))
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        self.items.iter().flat_map(|item| item.to_bytes()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb_gadget::hid::{flags, items, ReportDescriptor, ReportKind};
+
+    #[test]
+    fn round_trips() {
+        let builder = Builder::new()
+            .usage_page(0xff00)
+            .usage(0x01)
+            .collection(Collection::Vendor(0x80))
+            .push()
+            .report_id(0x21)
+            .logical_range(-32768, 32767)
+            .physical_range(-1, 1)
+            .unit_exponent(-2)
+            .unit(0x11)
+            .report_size(16)
+            .report_count(2)
+            .extended_usage(Usage {
+                page: 0x01,
+                id: 0x30,
+            })
+            .usage(0x02)
+            .input(flags::DATA | flags::VARIABLE | flags::BUFFERED_BYTES)
+            .pop()
+            .report_size(8)
+            .report_count(1)
+            .feature(flags::CONSTANT)
+            .end_collection();
+        let desc = builder.build();
+        assert_eq!(items(&desc).unwrap(), builder.items);
+
+        let parsed = ReportDescriptor::parse(&desc).unwrap();
+        let input = &parsed.fields[0];
+        assert_eq!(input.report_id, 0x21);
+        assert_eq!((input.logical_min, input.logical_max), (-32768, 32767));
+        assert_eq!(
+            input.usages,
+            [
+                Usage {
+                    page: 0x01,
+                    id: 0x30
+                },
+                Usage {
+                    page: 0xff00,
+                    id: 0x02
+                }
+            ]
+        );
+        assert_eq!(input.flags, 0x102);
+        // Pop restored the state from before the report ID
+        let feature = &parsed.fields[1];
+        assert_eq!((feature.kind, feature.report_id), (ReportKind::Feature, 0));
+        assert_eq!((feature.size, feature.count), (8, 1));
+    }
+
+    #[test]
+    fn collections() {
+        for id in 0..=0x06 {
+            assert_eq!(Collection::from_id(id).id(), id);
+        }
+        assert_eq!(Collection::from_id(0x80), Collection::Vendor(0x80));
+    }
+}
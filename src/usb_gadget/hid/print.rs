@@ -0,0 +1,165 @@
+use super::{items, usage_page, Collection, DescriptorError, Item, ItemType, Usage};
+use std::fmt::Write;
+
+fn page_name(page: u16) -> String {
+    match page {
+        usage_page::GENERIC_DESKTOP => "Generic Desktop".to_string(),
+        usage_page::SIMULATION => "Simulation Controls".to_string(),
+        usage_page::KEYBOARD => "Keyboard/Keypad".to_string(),
+        usage_page::LED => "LED".to_string(),
+        usage_page::BUTTON => "Button".to_string(),
+        usage_page::CONSUMER => "Consumer".to_string(),
+        0xff00..=0xffff => format!("Vendor Defined {:#06x}", page),
+        page => format!("{:#06x}", page),
+    }
+}
+
+fn flag_names(data: u32, output: bool) -> String {
+    let mut names = vec![
+        if data & 0x01 != 0 { "Const" } else { "Data" },
+        if data & 0x02 != 0 { "Var" } else { "Array" },
+        if data & 0x04 != 0 { "Rel" } else { "Abs" },
+    ];
+    let optional = [
+        (0x08, "Wrap"),
+        (0x10, "Non Linear"),
+        (0x20, "No Preferred"),
+        (0x40, "Null State"),
+        (0x100, "Buffered Bytes"),
+    ];
+    names.extend(
+        optional
+            .iter()
+            .filter(|(bit, _)| data & bit != 0)
+            .map(|(_, name)| *name),
+    );
+    // Bit 7 is reserved for inputs
+    if output && data & 0x80 != 0 {
+        names.push("Volatile");
+    }
+    names.join(", ")
+}
+
+/// Describes an item, e.g. "Usage Page (Generic Desktop)"
+fn describe(item: &Item, page: u16) -> String {
+    let usage = || Usage::from_item(page, item);
+    match (item.item_type, item.tag) {
+        (ItemType::Main, 0x8) => format!("Input ({})", flag_names(item.data, false)),
+        (ItemType::Main, 0x9) => format!("Output ({})", flag_names(item.data, true)),
+        (ItemType::Main, 0xb) => format!("Feature ({})", flag_names(item.data, true)),
+        (ItemType::Main, 0xa) => {
+            format!("Collection ({:?})", Collection::from_id(item.data as u8))
+        }
+        (ItemType::Main, 0xc) => "End Collection".to_string(),
+        (ItemType::Global, 0x0) => format!("Usage Page ({})", page_name(item.data as u16)),
+        (ItemType::Global, 0x1) => format!("Logical Minimum ({})", item.signed_data()),
+        (ItemType::Global, 0x2) => format!("Logical Maximum ({})", item.signed_data()),
+        (ItemType::Global, 0x3) => format!("Physical Minimum ({})", item.signed_data()),
+        (ItemType::Global, 0x4) => format!("Physical Maximum ({})", item.signed_data()),
+        (ItemType::Global, 0x5) => format!("Unit Exponent ({})", item.data),
+        (ItemType::Global, 0x6) => format!("Unit ({:#x})", item.data),
+        (ItemType::Global, 0x7) => format!("Report Size ({})", item.data),
+        (ItemType::Global, 0x8) => format!("Report ID ({:#04x})", item.data),
+        (ItemType::Global, 0x9) => format!("Report Count ({})", item.data),
+        (ItemType::Global, 0xa) => "Push".to_string(),
+        (ItemType::Global, 0xb) => "Pop".to_string(),
+        (ItemType::Local, 0x0) => format!("Usage ({})", usage()),
+        (ItemType::Local, 0x1) => format!("Usage Minimum ({})", usage()),
+        (ItemType::Local, 0x2) => format!("Usage Maximum ({})", usage()),
+        (ItemType::Local, 0x3) => format!("Designator Index ({})", item.data),
+        (ItemType::Local, 0x4) => format!("Designator Minimum ({})", item.data),
+        (ItemType::Local, 0x5) => format!("Designator Maximum ({})", item.data),
+        (ItemType::Local, 0x7) => format!("String Index ({})", item.data),
+        (ItemType::Local, 0x8) => format!("String Minimum ({})", item.data),
+        (ItemType::Local, 0x9) => format!("String Maximum ({})", item.data),
+        (ItemType::Local, 0xa) => format!("Delimiter ({})", item.data),
+        (item_type, tag) => format!("{:?} item {:#x} ({:#x})", item_type, tag, item.data),
+    }
+}
+
+/// Formats a descriptor as one item per line, with its bytes and a
+/// description indented by collection, e.g.
+///
+/// ```text
+/// 0x05, 0x01,                     // Usage Page (Generic Desktop)
+/// ```
+pub fn pretty_print(desc: &[u8]) -> Result<String, DescriptorError> {
+    let mut out = String::new();
+    let mut page = 0;
+    let mut depth: usize = 0;
+
+    for item in items(desc)? {
+        if item.item_type == ItemType::Main && item.tag == 0xc {
+            depth = depth.saturating_sub(1);
+        }
+        if item.item_type == ItemType::Global && item.tag == 0x0 {
+            page = item.data as u16;
+        }
+
+        let bytes: Vec<String> = item
+            .to_bytes()
+            .iter()
+            .map(|byte| format!("{:#04x},", byte))
+            .collect();
+        let _ = writeln!(
+            out,
+            "{:<32}// {}{}",
+            bytes.join(" "),
+            "  ".repeat(depth),
+            describe(&item, page)
+        );
+
+        if item.item_type == ItemType::Main && item.tag == 0xa {
+            depth += 1;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb_gadget::hid::{flags, generic_desktop, Builder};
+
+    #[test]
+    fn prints_items() {
+        let desc = Builder::new()
+            .usage_page(usage_page::GENERIC_DESKTOP)
+            .usage(generic_desktop::JOYSTICK)
+            .collection(Collection::Application)
+            .logical_range(-127, 127)
+            .usage(generic_desktop::X)
+            .report_size(8)
+            .report_count(1)
+            .input(flags::DATA | flags::VARIABLE | flags::NULL_STATE)
+            .end_collection()
+            .build();
+        let expected = "\
+0x05, 0x01,                     // Usage Page (Generic Desktop)
+0x09, 0x04,                     // Usage (Joystick)
+0xa1, 0x01,                     // Collection (Application)
+0x15, 0x81,                     //   Logical Minimum (-127)
+0x25, 0x7f,                     //   Logical Maximum (127)
+0x09, 0x30,                     //   Usage (X)
+0x75, 0x08,                     //   Report Size (8)
+0x95, 0x01,                     //   Report Count (1)
+0x81, 0x42,                     //   Input (Data, Var, Abs, Null State)
+0xc0,                           // End Collection
+";
+        assert_eq!(pretty_print(&desc).unwrap(), expected);
+    }
+
+    #[test]
+    fn prints_unknown_items() {
+        let out = pretty_print(&[0x06, 0x00, 0xff, 0xf4, 0x09, 0x01]).unwrap();
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            [
+                "0x06, 0x00, 0xff,               // Usage Page (Vendor Defined 0xff00)",
+                "0xf4,                           // Global item 0xf (0x0)",
+                "0x09, 0x01,                     // Usage (0xff00:0x0001)",
+            ]
+        );
+        assert_eq!(pretty_print(&[0x05]), Err(DescriptorError::Truncated(0)));
+    }
+}
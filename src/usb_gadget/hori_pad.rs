@@ -0,0 +1,47 @@
+use crate::usb_gadget::*;
+
+pub fn report_desc() -> Vec<u8> {
+    vec![
+        0x05, 0x01, 0x09, 0x05, 0xA1, 0x01, 0x15, 0x00, 0x25, 0x01, 0x35, 0x00, 0x45, 0x01, 0x75,
+        0x01, 0x95, 0x10, 0x05, 0x09, 0x19, 0x01, 0x29, 0x10, 0x81, 0x02, 0x05, 0x01, 0x25, 0x07,
+        0x46, 0x3B, 0x01, 0x75, 0x04, 0x95, 0x01, 0x65, 0x14, 0x09, 0x39, 0x81, 0x42, 0x65, 0x00,
+        0x95, 0x01, 0x81, 0x01, 0x26, 0xFF, 0x00, 0x46, 0xFF, 0x00, 0x09, 0x30, 0x09, 0x31, 0x09,
+        0x32, 0x09, 0x35, 0x75, 0x08, 0x95, 0x04, 0x81, 0x02, 0x06, 0x00, 0xFF, 0x09, 0x20, 0x95,
+        0x01, 0x81, 0x02, 0x0A, 0x21, 0x26, 0x95, 0x08, 0x91, 0x02, 0xC0,
+    ]
+}
+
+/// A single HORI wired pad, which the Switch accepts without any handshake
+pub fn hori_pad() -> Gadget {
+    let config = Config {
+        attributes: 0x80,
+        max_power: 500,
+        description: "HID Configuration".to_string(),
+
+        hid_functions: vec![0],
+    };
+
+    let func = HIDFunction {
+        report_desc: report_desc(),
+        report_length: 64,
+        ..Default::default()
+    };
+
+    Gadget {
+        device_max_packet_size: 64,
+
+        device_version: 0x100,
+        usb_version: 0x200,
+        product_id: 0x0092,
+        vendor_id: 0x0F0D,
+
+        serialnumber: "".to_string(),
+        product: "POKKEN CONTROLLER".to_string(),
+        manufacturer: "HORI CO.,LTD.".to_string(),
+
+        configs: vec![config],
+        hid_functions: vec![func],
+
+        ..Default::default()
+    }
+}
@@ -0,0 +1,52 @@
+use crate::usb_gadget::*;
+
+/// The boot protocol keyboard descriptor from the HID specification: a
+/// modifier byte, a reserved byte, six key slots and five LED outputs
+pub fn report_desc() -> Vec<u8> {
+    vec![
+        0x05, 0x01, 0x09, 0x06, 0xA1, 0x01, 0x05, 0x07, 0x19, 0xE0, 0x29, 0xE7, 0x15, 0x00, 0x25,
+        0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x95, 0x01, 0x75, 0x08, 0x81, 0x03, 0x95, 0x05,
+        0x75, 0x01, 0x05, 0x08, 0x19, 0x01, 0x29, 0x05, 0x91, 0x02, 0x95, 0x01, 0x75, 0x03, 0x91,
+        0x03, 0x95, 0x06, 0x75, 0x08, 0x15, 0x00, 0x25, 0x65, 0x05, 0x07, 0x19, 0x00, 0x29, 0x65,
+        0x81, 0x00, 0xC0,
+    ]
+}
+
+/// A HID function for a boot protocol keyboard
+pub fn keyboard_function() -> HIDFunction {
+    HIDFunction {
+        protocol: 1,
+        report_desc: report_desc(),
+        report_length: 8,
+        subclass: 1,
+    }
+}
+
+pub fn keyboard() -> Gadget {
+    let config = Config {
+        attributes: 0x80,
+        max_power: 100,
+        description: "HID Configuration".to_string(),
+
+        hid_functions: vec![0],
+    };
+
+    Gadget {
+        device_max_packet_size: 64,
+
+        device_version: 0x100,
+        usb_version: 0x200,
+        // Linux Foundation multifunction composite gadget
+        product_id: 0x0104,
+        vendor_id: 0x1D6B,
+
+        serialnumber: "deadbeef".to_string(),
+        product: "Keyboard".to_string(),
+        manufacturer: "controller-emulator".to_string(),
+
+        configs: vec![config],
+        hid_functions: vec![keyboard_function()],
+
+        ..Default::default()
+    }
+}
@@ -0,0 +1,216 @@
+use crate::usb_gadget::{Config, Gadget, GadgetEnv, GadgetError, HIDFunction, Result, Speed};
+use std::convert::TryFrom;
+use std::fs::{read, read_dir, read_link, read_to_string};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A gadget as it's currently configured in configfs
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveGadget {
+    /// The gadget's directory name under the configfs root
+    pub name: String,
+    pub gadget: Gadget,
+    pub udc: Option<String>,
+}
+
+fn read_attribute(path: &Path) -> Result<String> {
+    match read_to_string(path) {
+        Ok(value) => Ok(value.trim_end_matches('\n').to_string()),
+        Err(e) => Err(GadgetError::io(path, e)),
+    }
+}
+
+/// Reads a numeric attribute, which the kernel shows as either hex with a
+/// 0x prefix or decimal depending on the attribute
+fn read_int(path: &Path) -> Result<u32> {
+    let value = read_attribute(path)?;
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| GadgetError::Io {
+        path: path.to_path_buf(),
+        source: io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} isn't a number", value),
+        ),
+    })
+}
+
+fn read_byte(path: &Path) -> Result<u8> {
+    let value = read_int(path)?;
+    u8::try_from(value).map_err(|_| GadgetError::Io {
+        path: path.to_path_buf(),
+        source: io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} doesn't fit in a byte", value),
+        ),
+    })
+}
+
+/// Reads a string attribute if the language directory exists
+fn read_string(path: &Path, name: &str) -> Result<String> {
+    if !path.is_dir() {
+        return Ok(String::new());
+    }
+    read_attribute(&path.join(name))
+}
+
+/// The entries of a directory whose names start with `prefix`, ordered by
+/// the number at the end of the name so that e.g. c.10 comes after c.2
+fn numbered_entries(path: &Path, prefix: &str) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for entry in read_dir(path).map_err(|e| GadgetError::io(path, e))? {
+        let entry = entry.map_err(|e| GadgetError::io(path, e))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(prefix) {
+            continue;
+        }
+        let number = name
+            .rsplit('.')
+            .next()
+            .and_then(|n| n.parse::<u32>().ok())
+            .unwrap_or(u32::MAX);
+        entries.push((number, name, entry.path()));
+    }
+    entries.sort();
+    Ok(entries.into_iter().map(|(_, _, path)| path).collect())
+}
+
+fn load_hid_function(path: &Path) -> Result<HIDFunction> {
+    let report_desc_path = path.join("report_desc");
+    Ok(HIDFunction {
+        protocol: read_int(&path.join("protocol"))?,
+        report_desc: read(&report_desc_path).map_err(|e| GadgetError::io(&report_desc_path, e))?,
+        report_length: read_int(&path.join("report_length"))?,
+        subclass: read_int(&path.join("subclass"))?,
+    })
+}
+
+/// Reads a config, mapping the functions linked into it to their index in
+/// `functions`. Links to functions that aren't HID are skipped.
+fn load_config(path: &Path, functions: &[PathBuf]) -> Result<Config> {
+    let mut hid_functions = Vec::new();
+    for entry in read_dir(path).map_err(|e| GadgetError::io(path, e))? {
+        let entry = entry.map_err(|e| GadgetError::io(path, e))?;
+        let target = match read_link(entry.path()) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        let index = functions
+            .iter()
+            .position(|f| f.file_name() == target.file_name());
+        if let Some(index) = index {
+            hid_functions.push(index as u32);
+        }
+    }
+    hid_functions.sort_unstable();
+
+    Ok(Config {
+        attributes: read_byte(&path.join("bmAttributes"))?,
+        max_power: read_int(&path.join("MaxPower"))?,
+        description: read_string(&path.join("strings/0x409"), "configuration")?,
+        hid_functions,
+    })
+}
+
+impl Speed {
+    fn parse(speed: &str) -> Option<Speed> {
+        match speed {
+            "low-speed" => Some(Speed::LowSpeed),
+            "full-speed" => Some(Speed::FullSpeed),
+            "high-speed" => Some(Speed::HighSpeed),
+            "super-speed" => Some(Speed::SuperSpeed),
+            _ => None,
+        }
+    }
+}
+
+impl GadgetEnv {
+    /// Reads back the gadget at <configfs>/<name>. Only HID functions and
+    /// US English strings are read, as those are all a `Gadget` can hold.
+    pub fn load(&self, name: &str) -> Result<LiveGadget> {
+        self.check_mounted()?;
+        let base_path = self.gadget_path(name)?;
+        if !base_path.is_dir() {
+            return Err(GadgetError::NoSuchGadget(name.to_string()));
+        }
+
+        let function_paths = numbered_entries(&base_path.join("functions"), "hid.")?;
+        let mut hid_functions = Vec::new();
+        for path in &function_paths {
+            hid_functions.push(load_hid_function(path)?);
+        }
+
+        let mut configs = Vec::new();
+        for path in numbered_entries(&base_path.join("configs"), "")? {
+            configs.push(load_config(&path, &function_paths)?);
+        }
+
+        let strings_path = base_path.join("strings/0x409");
+        let max_speed = read_attribute(&base_path.join("max_speed"))?;
+        let gadget = Gadget {
+            max_speed: Speed::parse(&max_speed).unwrap_or_default(),
+            device_class: read_byte(&base_path.join("bDeviceClass"))?,
+            device_sub_class: read_byte(&base_path.join("bDeviceSubClass"))?,
+            device_protocol: read_byte(&base_path.join("bDeviceProtocol"))?,
+            device_max_packet_size: read_byte(&base_path.join("bMaxPacketSize0"))?,
+
+            device_version: read_int(&base_path.join("bcdDevice"))?,
+            usb_version: read_int(&base_path.join("bcdUSB"))?,
+            product_id: read_int(&base_path.join("idProduct"))?,
+            vendor_id: read_int(&base_path.join("idVendor"))?,
+
+            configs,
+            hid_functions,
+
+            serialnumber: read_string(&strings_path, "serialnumber")?,
+            product: read_string(&strings_path, "product")?,
+            manufacturer: read_string(&strings_path, "manufacturer")?,
+        };
+
+        Ok(LiveGadget {
+            name: name.to_string(),
+            gadget,
+            udc: self.bound_udc(name)?,
+        })
+    }
+
+    /// Loads every gadget under the configfs root, sorted by name. Each
+    /// gadget is loaded separately, so one that can't be read doesn't hide
+    /// the others.
+    pub fn gadgets(&self) -> Result<Vec<(String, Result<LiveGadget>)>> {
+        self.check_mounted()?;
+        let mut names = Vec::new();
+        for entry in read_dir(&self.configfs).map_err(|e| GadgetError::io(&self.configfs, e))? {
+            let entry = entry.map_err(|e| GadgetError::io(&self.configfs, e))?;
+            if entry.path().is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names
+            .into_iter()
+            .map(|name| {
+                let live = self.load(&name);
+                (name, live)
+            })
+            .collect())
+    }
+}
+
+impl Gadget {
+    /// Reads back the gadget at /sys/kernel/config/usb_gadget/<name>
+    pub fn load(name: &str) -> Result<LiveGadget> {
+        GadgetEnv::default().load(name)
+    }
+}
+
+/// Loads every gadget in /sys/kernel/config/usb_gadget. See
+/// `GadgetEnv::gadgets`.
+pub fn gadgets() -> Result<Vec<(String, Result<LiveGadget>)>> {
+    GadgetEnv::default().gadgets()
+}
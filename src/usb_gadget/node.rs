@@ -0,0 +1,166 @@
+use crate::usb_gadget::{function_dir, GadgetEnv, GadgetError, GadgetHandle, Result};
+use std::fs::read_to_string;
+use std::io;
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// How often to check whether udev has created a device node
+const POLL_PERIOD: Duration = Duration::from_millis(10);
+
+impl GadgetEnv {
+    /// The /dev node of the gadget's HID function at `index`. The kernel
+    /// hands out hidg minors in creation order across all gadgets, so this
+    /// looks the node up from the function's major:minor rather than
+    /// assuming /dev/hidg<index>.
+    pub fn hid_device(&self, name: &str, index: usize) -> Result<PathBuf> {
+        let hid_path = self.gadget_path(name)?.join(function_dir(index));
+        if !hid_path.is_dir() {
+            return Err(GadgetError::NoSuchFunction(index));
+        }
+        let dev_path = hid_path.join("dev");
+        let dev = read_to_string(&dev_path).map_err(|e| GadgetError::io(&dev_path, e))?;
+        let dev = dev.trim();
+
+        // The uevent names the node udev creates, as in DEVNAME=hidg0
+        let uevent_path = self.char_devices.join(dev).join("uevent");
+        let dev_name = read_to_string(&uevent_path).ok().and_then(|uevent| {
+            uevent
+                .lines()
+                .find_map(|line| line.strip_prefix("DEVNAME="))
+                .map(|name| name.to_string())
+        });
+        let dev_name = match dev_name {
+            Some(dev_name) => dev_name,
+            // The uevent only exists while the gadget is bound, but f_hid
+            // always names its nodes after the minor
+            None => match dev.split(':').nth(1) {
+                Some(minor) => format!("hidg{}", minor),
+                None => {
+                    return Err(GadgetError::Io {
+                        path: dev_path,
+                        source: io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{:?} isn't major:minor", dev),
+                        ),
+                    })
+                }
+            },
+        };
+        Ok(self.dev.join(dev_name))
+    }
+
+    /// Like `hid_device`, but waits up to `timeout` for udev to create the
+    /// node after the gadget is bound
+    pub fn wait_for_hid_device(
+        &self,
+        name: &str,
+        index: usize,
+        timeout: Duration,
+    ) -> Result<PathBuf> {
+        let start = Instant::now();
+        loop {
+            let path = self.hid_device(name, index)?;
+            if path.exists() {
+                return Ok(path);
+            }
+            if start.elapsed() >= timeout {
+                return Err(GadgetError::NodeTimeout(path));
+            }
+            sleep(POLL_PERIOD);
+        }
+    }
+}
+
+impl GadgetHandle {
+    /// The /dev node of the HID function at `index`. See
+    /// `GadgetEnv::hid_device`.
+    pub fn hid_device(&self, index: usize) -> Result<PathBuf> {
+        self.env().hid_device(self.name(), index)
+    }
+
+    /// Waits up to `timeout` for the node of the HID function at `index` to
+    /// be created, so it can be opened straight after `instantiate`
+    pub fn wait_for_hid_device(&self, index: usize, timeout: Duration) -> Result<PathBuf> {
+        self.env().wait_for_hid_device(self.name(), index, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb_gadget::keyboard::keyboard;
+    use crate::usb_gadget::tests::TestEnv;
+    use std::fs;
+
+    /// A keyboard gadget whose HID function has the device number `dev`
+    fn test_env(test: &str, dev: &str) -> TestEnv {
+        let test = TestEnv::new(test);
+        keyboard().create_config_in(&test.env, "keyboard").unwrap();
+        let function_path = test
+            .env
+            .gadget_path("keyboard")
+            .unwrap()
+            .join(function_dir(0));
+        fs::write(function_path.join("dev"), dev).unwrap();
+        test
+    }
+
+    #[test]
+    fn node_named_by_uevent() {
+        let test = test_env("node_named_by_uevent", "240:3\n");
+        let char_path = test.env.char_devices().join("240:3");
+        fs::create_dir_all(&char_path).unwrap();
+        fs::write(
+            char_path.join("uevent"),
+            "MAJOR=240\nMINOR=3\nDEVNAME=hidg_keyboard\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            test.env.hid_device("keyboard", 0).unwrap(),
+            test.env.dev().join("hidg_keyboard")
+        );
+    }
+
+    #[test]
+    fn node_named_by_minor() {
+        let test = test_env("node_named_by_minor", "240:3\n");
+        assert_eq!(
+            test.env.hid_device("keyboard", 0).unwrap(),
+            test.env.dev().join("hidg3")
+        );
+    }
+
+    #[test]
+    fn bad_functions() {
+        let test = test_env("bad_functions", "hidg3\n");
+        assert!(matches!(
+            test.env.hid_device("keyboard", 0),
+            Err(GadgetError::Io { .. })
+        ));
+        assert!(matches!(
+            test.env.hid_device("keyboard", 1),
+            Err(GadgetError::NoSuchFunction(1))
+        ));
+    }
+
+    #[test]
+    fn waits_for_node() {
+        let test = test_env("waits_for_node", "240:3\n");
+        let node = test.env.dev().join("hidg3");
+        match test.env.wait_for_hid_device("keyboard", 0, POLL_PERIOD * 3) {
+            Err(GadgetError::NodeTimeout(path)) => assert_eq!(path, node),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+
+        fs::create_dir_all(test.env.dev()).unwrap();
+        fs::write(&node, "").unwrap();
+        assert_eq!(
+            test.env
+                .wait_for_hid_device("keyboard", 0, Duration::from_secs(1))
+                .unwrap(),
+            node
+        );
+    }
+}
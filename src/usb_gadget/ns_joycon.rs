@@ -0,0 +1,10 @@
+use crate::usb_gadget::ns_procon::ns_controllers;
+use crate::usb_gadget::*;
+
+pub fn ns_joycons_l() -> Gadget {
+    ns_controllers(0x2006, "Joy-Con (L)")
+}
+
+pub fn ns_joycons_r() -> Gadget {
+    ns_controllers(0x2007, "Joy-Con (R)")
+}
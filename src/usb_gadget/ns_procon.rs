@@ -20,6 +20,12 @@ pub fn report_desc() -> Vec<u8> {
 }
 
 pub fn ns_procons() -> Gadget {
+    ns_controllers(0x2009, "Pro Controller")
+}
+
+/// A gadget with four HID functions that each speak the Switch controller
+/// protocol, identifying as the given product
+pub(crate) fn ns_controllers(product_id: u32, product: &str) -> Gadget {
     let config = Config {
         attributes: 0x80,
         description: "HID Configuration".to_string(),
@@ -30,17 +36,24 @@ pub fn ns_procons() -> Gadget {
 
     let func = HIDFunction {
         report_desc: report_desc(),
+        // As on a real Pro Controller, and the most a full-speed interrupt
+        // endpoint can carry. This leaves no room for the 362 byte NFC/IR
+        // reports the Switch reads amiibo through, so NsProcon refuses
+        // that mode and won't take a tag on these gadgets.
         report_length: 64,
         ..Default::default()
     };
 
     Gadget {
+        device_max_packet_size: 64,
+
         device_version: 0x210,
-        product_id: 0x2009,
+        usb_version: 0x200,
+        product_id,
         vendor_id: 0x057E,
 
         serialnumber: "deadbeef".to_string(),
-        product: "Pro Controller".to_string(),
+        product: product.to_string(),
         manufacturer: "Nintendo Co., Ltd".to_string(),
 
         configs: vec![config],
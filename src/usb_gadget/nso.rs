@@ -0,0 +1,20 @@
+use crate::usb_gadget::ns_procon::ns_controllers;
+use crate::usb_gadget::*;
+
+// Both NES controllers identify with the Joy-Con (R) product ID and are told
+// apart by the device type in their device info reply
+pub fn nes_controllers() -> Gadget {
+    ns_controllers(0x2007, "NES Controller")
+}
+
+pub fn snes_controllers() -> Gadget {
+    ns_controllers(0x2017, "SNES Controller")
+}
+
+pub fn n64_controllers() -> Gadget {
+    ns_controllers(0x2019, "N64 Controller")
+}
+
+pub fn genesis_controllers() -> Gadget {
+    ns_controllers(0x201e, "MD/Gen Control Pad")
+}
@@ -0,0 +1,334 @@
+use crate::usb_gadget::{
+    config_dir, function_dir, link_function, remove_node, unlink_function, write_attribute,
+    Attributes, Gadget, GadgetEnv, GadgetError, LiveGadget, Result,
+};
+use std::path::{Path, PathBuf};
+
+/// Something `reconcile` changed in a live gadget
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The gadget didn't exist, or wasn't laid out the way `create_config`
+    /// lays it out, so it was written from scratch
+    Created,
+    /// An attribute file was rewritten, relative to the gadget's directory
+    Attribute(PathBuf),
+    AddedFunction(usize),
+    RemovedFunction(usize),
+    AddedConfig(usize),
+    RemovedConfig(usize),
+    Linked {
+        config: usize,
+        function: usize,
+    },
+    Unlinked {
+        config: usize,
+        function: usize,
+    },
+    /// The gadget was unbound from this UDC to make the changes, and bound
+    /// to it again afterwards
+    Rebound(String),
+}
+
+/// The attributes in `desired` whose contents differ from `live`
+fn changed(live: Attributes, desired: Attributes) -> Attributes {
+    desired
+        .into_iter()
+        .filter(|attribute| !live.contains(attribute))
+        .collect()
+}
+
+/// Whether the functions and configs `load` found are the ones
+/// `create_config` would have written, so they can be changed in place
+fn has_own_layout(base_path: &Path, live: &LiveGadget) -> bool {
+    let functions = (0..live.gadget.hid_functions.len()).map(function_dir);
+    let configs = (0..live.gadget.configs.len()).map(config_dir);
+    functions
+        .chain(configs)
+        .all(|dir| base_path.join(dir).is_dir())
+}
+
+impl GadgetEnv {
+    /// Brings the gadget at <configfs>/<name> in line with `gadget`, only
+    /// rewriting what differs. The host only sees the changes once the
+    /// gadget is bound again, so a bound gadget is unbound and rebound
+    /// around them, but left alone if nothing changed. Functions that are
+    /// kept keep their /dev/hidgN nodes.
+    pub fn reconcile(&self, gadget: &Gadget, name: &str) -> Result<Vec<Change>> {
+        gadget.validate()?;
+        let live = match self.load(name) {
+            Ok(live) => live,
+            Err(GadgetError::NoSuchGadget(_)) => {
+                gadget.create_config_in(self, name)?;
+                return Ok(vec![Change::Created]);
+            }
+            Err(e) => return Err(e),
+        };
+        let base_path = self.gadget_path(name)?;
+
+        if !has_own_layout(&base_path, &live) {
+            let created = gadget.create_config_in(self, name);
+            return self.rebind(name, live.udc, created, vec![Change::Created]);
+        }
+        if live.gadget == *gadget {
+            return Ok(Vec::new());
+        }
+
+        let mut changes = Vec::new();
+        let live_functions = &live.gadget.hid_functions;
+        let live_configs = &live.gadget.configs;
+
+        if live.udc.is_some() {
+            self.deactivate(name)?;
+        }
+
+        // Once unbound, the gadget is bound again even if a step fails, so
+        // a failed reconcile doesn't leave the host without its device
+        let mut edit = || -> Result<()> {
+            // The kernel won't change a function's attributes while it's
+            // linked into a config, so changed functions are unlinked until
+            // they're rewritten
+            let changed_functions: Vec<(usize, Attributes)> = live_functions
+                .iter()
+                .zip(&gadget.hid_functions)
+                .map(|(live, desired)| changed(live.attribute_files(), desired.attribute_files()))
+                .enumerate()
+                .filter(|(_, attributes)| !attributes.is_empty())
+                .collect();
+            let is_changed = |function: u32| {
+                changed_functions
+                    .iter()
+                    .any(|(i, _)| *i == function as usize)
+            };
+
+            let mut linked = Vec::new();
+            for (i, config) in live_configs.iter().enumerate() {
+                let desired = gadget.configs.get(i).map(|c| &c.hid_functions[..]);
+                let mut kept = Vec::new();
+                for &function in &config.hid_functions {
+                    let wanted = desired.is_some_and(|d| d.contains(&function));
+                    if wanted && !is_changed(function) {
+                        kept.push(function);
+                        continue;
+                    }
+                    unlink_function(&base_path, i, function as usize)?;
+                    if !wanted {
+                        changes.push(Change::Unlinked {
+                            config: i,
+                            function: function as usize,
+                        });
+                    }
+                }
+                linked.push(kept);
+            }
+
+            for i in gadget.configs.len()..live_configs.len() {
+                let config_path = base_path.join(config_dir(i));
+                remove_node(&config_path.join("strings/0x409"))?;
+                remove_node(&config_path)?;
+                changes.push(Change::RemovedConfig(i));
+            }
+
+            for i in gadget.hid_functions.len()..live_functions.len() {
+                remove_node(&base_path.join(function_dir(i)))?;
+                changes.push(Change::RemovedFunction(i));
+            }
+
+            for (i, attributes) in changed_functions {
+                let hid_dir = function_dir(i);
+                for (name, contents) in attributes {
+                    write_attribute(&base_path.join(&hid_dir), name, &contents)?;
+                    changes.push(Change::Attribute(Path::new(&hid_dir).join(name)));
+                }
+            }
+
+            for (i, function) in gadget.hid_functions.iter().enumerate() {
+                if i < live_functions.len() {
+                    continue;
+                }
+                let hid_path = base_path.join(function_dir(i));
+                for (name, contents) in function.attribute_files() {
+                    write_attribute(&hid_path, name, &contents)?;
+                }
+                changes.push(Change::AddedFunction(i));
+            }
+
+            let attributes = changed(live.gadget.attribute_files(), gadget.attribute_files());
+            for (name, contents) in attributes {
+                write_attribute(&base_path, name, &contents)?;
+                changes.push(Change::Attribute(PathBuf::from(name)));
+            }
+
+            for (i, config) in gadget.configs.iter().enumerate() {
+                let config_dir = config_dir(i);
+                let config_path = base_path.join(&config_dir);
+                match live_configs.get(i) {
+                    Some(live) => {
+                        let attributes = changed(live.attribute_files(), config.attribute_files());
+                        for (name, contents) in attributes {
+                            write_attribute(&config_path, name, &contents)?;
+                            changes.push(Change::Attribute(Path::new(&config_dir).join(name)));
+                        }
+                    }
+                    None => {
+                        for (name, contents) in config.attribute_files() {
+                            write_attribute(&config_path, name, &contents)?;
+                        }
+                        changes.push(Change::AddedConfig(i));
+                    }
+                }
+
+                let was_linked = live_configs.get(i).map(|c| &c.hid_functions[..]);
+                let kept = linked.get(i).map(|k| &k[..]).unwrap_or_default();
+                for &function in &config.hid_functions {
+                    if kept.contains(&function) {
+                        continue;
+                    }
+                    link_function(&base_path, i, function as usize)?;
+                    if !was_linked.is_some_and(|l| l.contains(&function)) {
+                        changes.push(Change::Linked {
+                            config: i,
+                            function: function as usize,
+                        });
+                    }
+                }
+            }
+            Ok(())
+        };
+        let edited = edit();
+        self.rebind(name, live.udc, edited, changes)
+    }
+
+    /// Binds a gadget that was unbound to be edited to its UDC again, even
+    /// if the edit failed. The edit's error comes first; a bind that fails
+    /// after it is only logged.
+    fn rebind(
+        &self,
+        name: &str,
+        udc: Option<String>,
+        edited: Result<()>,
+        mut changes: Vec<Change>,
+    ) -> Result<Vec<Change>> {
+        if let Some(udc) = udc {
+            match self.bind(name, &udc) {
+                Ok(()) => changes.push(Change::Rebound(udc)),
+                Err(e) if edited.is_ok() => return Err(e),
+                Err(e) => log::error!("couldn't bind {} to {} again: {}", name, udc, e),
+            }
+        }
+        edited.map(|()| changes)
+    }
+}
+
+impl Gadget {
+    /// Brings the gadget at /sys/kernel/config/usb_gadget/<name> in line
+    /// with this one, creating it if it doesn't exist. See
+    /// `GadgetEnv::reconcile`.
+    pub fn reconcile(&self, name: &str) -> Result<Vec<Change>> {
+        GadgetEnv::default().reconcile(self, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb_gadget::keyboard::keyboard;
+    use crate::usb_gadget::ns_procon::ns_procons;
+    use crate::usb_gadget::tests::TestEnv;
+    use std::fs;
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    const UDC: &str = "dummy_udc.0";
+
+    #[test]
+    fn unchanged_gadget_is_left_alone() {
+        let test = TestEnv::new("unchanged_gadget_is_left_alone");
+        keyboard().create_config_in(&test.env, "keyboard").unwrap();
+        test.env.bind("keyboard", UDC).unwrap();
+
+        assert_eq!(test.env.reconcile(&keyboard(), "keyboard").unwrap(), []);
+        assert_eq!(
+            test.env.bound_udc("keyboard").unwrap().as_deref(),
+            Some(UDC)
+        );
+    }
+
+    #[test]
+    fn serial_change_only_rewrites_serial() {
+        let test = TestEnv::new("serial_change_only_rewrites_serial");
+        keyboard().create_config_in(&test.env, "keyboard").unwrap();
+        test.env.bind("keyboard", UDC).unwrap();
+
+        let mut gadget = keyboard();
+        gadget.serialnumber = "000000000002".to_string();
+        assert_eq!(
+            test.env.reconcile(&gadget, "keyboard").unwrap(),
+            [
+                Change::Attribute(PathBuf::from("strings/0x409/serialnumber")),
+                Change::Rebound(UDC.to_string()),
+            ]
+        );
+        assert_eq!(test.env.load("keyboard").unwrap().gadget, gadget);
+    }
+
+    #[test]
+    fn added_function_keeps_others_linked() {
+        let test = TestEnv::new("added_function_keeps_others_linked");
+        ns_procons().create_config_in(&test.env, "procons").unwrap();
+        let config_path = test.env.gadget_path("procons").unwrap().join(config_dir(0));
+        let link_inode = |function: usize| {
+            let link_path = config_path.join(format!("hid.usb.{}", function));
+            fs::symlink_metadata(link_path).unwrap().ino()
+        };
+        let inodes: Vec<u64> = (0..4).map(link_inode).collect();
+
+        let mut gadget = ns_procons();
+        gadget.hid_functions.push(gadget.hid_functions[0].clone());
+        gadget.configs[0].hid_functions.push(4);
+        assert_eq!(
+            test.env.reconcile(&gadget, "procons").unwrap(),
+            [
+                Change::AddedFunction(4),
+                Change::Linked {
+                    config: 0,
+                    function: 4
+                },
+            ]
+        );
+        assert_eq!((0..4).map(link_inode).collect::<Vec<_>>(), inodes);
+        assert_eq!(test.env.load("procons").unwrap().gadget, gadget);
+    }
+
+    #[test]
+    fn failed_reconcile_rebinds() {
+        let test = TestEnv::new("failed_reconcile_rebinds");
+        ns_procons().create_config_in(&test.env, "procons").unwrap();
+        test.env.bind("procons", UDC).unwrap();
+        // Something that isn't a link where the new function's link goes
+        let config_path = test.env.gadget_path("procons").unwrap().join(config_dir(0));
+        fs::write(config_path.join("hid.usb.4"), "").unwrap();
+
+        let mut gadget = ns_procons();
+        gadget.hid_functions.push(gadget.hid_functions[0].clone());
+        gadget.configs[0].hid_functions.push(4);
+        assert!(matches!(
+            test.env.reconcile(&gadget, "procons"),
+            Err(GadgetError::Io { .. })
+        ));
+        assert_eq!(test.env.bound_udc("procons").unwrap().as_deref(), Some(UDC));
+
+        // A gadget laid out by something else is rewritten from scratch,
+        // which fails here on a function directory that can't be removed
+        let base_path = test.env.gadget_path("procons").unwrap();
+        fs::remove_file(config_path.join("hid.usb.4")).unwrap();
+        fs::remove_file(config_path.join("hid.usb.0")).unwrap();
+        let function_path = base_path.join("functions/hid.other");
+        fs::rename(base_path.join(function_dir(0)), &function_path).unwrap();
+        symlink(&function_path, config_path.join("hid.other")).unwrap();
+        fs::create_dir(function_path.join("unknown")).unwrap();
+        assert!(matches!(
+            test.env.reconcile(&ns_procons(), "procons"),
+            Err(GadgetError::Io { .. })
+        ));
+        assert_eq!(test.env.bound_udc("procons").unwrap().as_deref(), Some(UDC));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::usb_gadget::{GadgetEnv, GadgetError, Result};
+use std::fs::{read_dir, read_to_string, write};
+use std::io::ErrorKind;
+
+/// The USB device state a UDC reports in sysfs
+#[derive(Debug, Clone, PartialEq)]
+pub enum UdcState {
+    NotAttached,
+    Attached,
+    Powered,
+    Reconnecting,
+    Unauthenticated,
+    Default,
+    Addressed,
+    /// Enumerated by the host, so the gadget's functions are usable
+    Configured,
+    Suspended,
+    Other(String),
+}
+
+impl UdcState {
+    fn parse(state: &str) -> UdcState {
+        match state {
+            "not attached" => UdcState::NotAttached,
+            "attached" => UdcState::Attached,
+            "powered" => UdcState::Powered,
+            "reconnecting" => UdcState::Reconnecting,
+            "unauthenticated" => UdcState::Unauthenticated,
+            "default" => UdcState::Default,
+            "addressed" => UdcState::Addressed,
+            "configured" => UdcState::Configured,
+            "suspended" => UdcState::Suspended,
+            state => UdcState::Other(state.to_string()),
+        }
+    }
+}
+
+/// A USB device controller that gadgets can be bound to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Udc {
+    pub name: String,
+    pub state: UdcState,
+    /// The gadget bound to this UDC, if it's one under our configfs root
+    pub gadget: Option<String>,
+    /// The gadget driver sysfs reports as bound to this UDC. Unlike
+    /// `gadget`, this is also set for legacy drivers like g_ether and for
+    /// gadgets under other configfs roots. Empty if a driver is bound but
+    /// the kernel doesn't say which.
+    pub function: Option<String>,
+}
+
+impl Udc {
+    /// Whether no gadget or legacy gadget driver is bound to this UDC
+    pub fn is_free(&self) -> bool {
+        self.gadget.is_none() && self.function.is_none()
+    }
+}
+
+impl GadgetEnv {
+    /// Lists the UDCs on the system, sorted by name
+    pub fn udcs(&self) -> Result<Vec<Udc>> {
+        let bindings = self.bindings()?;
+        let mut udcs = Vec::new();
+        let entries = read_dir(&self.udc_class).map_err(|e| GadgetError::io(&self.udc_class, e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| GadgetError::io(&self.udc_class, e))?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let state = read_to_string(entry.path().join("state"))
+                .map(|state| UdcState::parse(state.trim()))
+                .unwrap_or(UdcState::Other(String::new()));
+            let function = match read_to_string(entry.path().join("function")) {
+                Ok(function) => Some(function.trim().to_string()).filter(|f| !f.is_empty()),
+                // Kernels without the function attribute only leave the
+                // state at "not attached" while no driver is bound
+                Err(_) if state == UdcState::NotAttached => None,
+                Err(_) => Some(String::new()),
+            };
+            let gadget = bindings
+                .iter()
+                .find(|(_, udc)| *udc == name)
+                .map(|(gadget, _)| gadget.clone());
+            udcs.push(Udc {
+                name,
+                state,
+                gadget,
+                function,
+            });
+        }
+        udcs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(udcs)
+    }
+
+    /// Binds a gadget to the named UDC
+    pub fn bind(&self, name: &str, udc: &str) -> Result<()> {
+        self.check_mounted()?;
+        if !self.udc_class.join(udc).exists() {
+            return Err(GadgetError::NoSuchUdc(udc.to_string()));
+        }
+        let path = self.gadget_path(name)?.join("UDC");
+        write(&path, udc).map_err(|e| GadgetError::bind(&path, udc, e))
+    }
+
+    /// Binds a gadget to the first UDC no other gadget or gadget driver is
+    /// using
+    pub fn bind_any(&self, name: &str) -> Result<String> {
+        let udc = self
+            .udcs()?
+            .into_iter()
+            .find(Udc::is_free)
+            .ok_or(GadgetError::NoFreeUdc)?;
+        self.bind(name, &udc.name)?;
+        Ok(udc.name)
+    }
+
+    /// The UDC a gadget is bound to, if any
+    pub fn bound_udc(&self, name: &str) -> Result<Option<String>> {
+        let path = self.gadget_path(name)?.join("UDC");
+        let udc = match read_to_string(&path) {
+            Ok(udc) => udc,
+            // Not created yet, so not bound
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(GadgetError::io(&path, e)),
+        };
+        let udc = udc.trim();
+        Ok(if udc.is_empty() {
+            None
+        } else {
+            Some(udc.to_string())
+        })
+    }
+
+    /// Pairs of gadget and UDC for every bound gadget under the configfs root
+    fn bindings(&self) -> Result<Vec<(String, String)>> {
+        let mut bindings = Vec::new();
+        if !self.configfs.exists() {
+            return Ok(bindings);
+        }
+        let entries = read_dir(&self.configfs).map_err(|e| GadgetError::io(&self.configfs, e))?;
+        for entry in entries {
+            let entry = entry.map_err(|e| GadgetError::io(&self.configfs, e))?;
+            let gadget = entry.file_name().to_string_lossy().into_owned();
+            if let Ok(Some(udc)) = self.bound_udc(&gadget) {
+                bindings.push((gadget, udc));
+            }
+        }
+        Ok(bindings)
+    }
+}
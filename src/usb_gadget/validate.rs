@@ -0,0 +1,338 @@
+use crate::usb_gadget::hid::{self, DescriptorError};
+use crate::usb_gadget::{Gadget, Speed};
+use std::error::Error;
+use std::fmt;
+
+/// Longest string a USB string descriptor can hold, in UTF-16 code units
+const MAX_STRING_LEN: usize = 126;
+
+/// Most current a configuration can draw from the bus, in mA. USB 3
+/// allows 900 mA where USB 2 allows 500.
+fn max_power_limit(speed: Speed) -> u32 {
+    match speed {
+        Speed::SuperSpeed => 900,
+        Speed::LowSpeed | Speed::FullSpeed | Speed::HighSpeed => 500,
+    }
+}
+
+/// A reason a `Gadget` can't be written to configfs
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    NoConfigs,
+    /// bMaxPacketSize0 isn't 8, 16, 32 or 64
+    InvalidMaxPacketSize(u8),
+    /// A version field isn't binary coded decimal
+    InvalidBcd {
+        field: &'static str,
+        value: u32,
+    },
+    StringTooLong {
+        field: &'static str,
+        len: usize,
+    },
+    /// A config has no functions
+    EmptyConfig {
+        config: usize,
+    },
+    /// A config refers to a HID function index that doesn't exist
+    MissingHidFunction {
+        config: usize,
+        function: u32,
+    },
+    /// bmAttributes must have bit 7 set and its low 5 bits clear
+    InvalidAttributes {
+        config: usize,
+        attributes: u8,
+    },
+    /// MaxPower is over what the bus provides at the gadget's max speed
+    MaxPowerTooHigh {
+        config: usize,
+        max_power: u32,
+        limit: u32,
+    },
+    ZeroReportLength {
+        function: usize,
+    },
+    InvalidReportDescriptor {
+        function: usize,
+        error: DescriptorError,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::NoConfigs => write!(f, "gadget has no configs"),
+            ValidationError::InvalidMaxPacketSize(size) => {
+                write!(f, "bMaxPacketSize0 is {}, must be 8, 16, 32 or 64", size)
+            }
+            ValidationError::InvalidBcd { field, value } => {
+                write!(f, "{} {:#06x} isn't binary coded decimal", field, value)
+            }
+            ValidationError::StringTooLong { field, len } => write!(
+                f,
+                "{} is {} characters, the limit is {}",
+                field, len, MAX_STRING_LEN
+            ),
+            ValidationError::EmptyConfig { config } => {
+                write!(f, "config {} has no functions", config)
+            }
+            ValidationError::MissingHidFunction { config, function } => write!(
+                f,
+                "config {} uses HID function {}, which doesn't exist",
+                config, function
+            ),
+            ValidationError::InvalidAttributes { config, attributes } => write!(
+                f,
+                "config {} has invalid bmAttributes {:#04x}",
+                config, attributes
+            ),
+            ValidationError::MaxPowerTooHigh {
+                config,
+                max_power,
+                limit,
+            } => write!(
+                f,
+                "config {} draws {} mA, the limit is {}",
+                config, max_power, limit
+            ),
+            ValidationError::ZeroReportLength { function } => {
+                write!(f, "HID function {} has a report length of 0", function)
+            }
+            ValidationError::InvalidReportDescriptor { function, error } => {
+                write!(f, "HID function {}: {}", function, error)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+fn is_bcd(value: u32) -> bool {
+    value <= 0xffff && (0..4).all(|i| (value >> (i * 4)) & 0xf <= 9)
+}
+
+impl Gadget {
+    /// Checks the gadget for mistakes configfs would reject or that would
+    /// leave the host unable to enumerate it
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if ![8, 16, 32, 64].contains(&self.device_max_packet_size) {
+            return Err(ValidationError::InvalidMaxPacketSize(
+                self.device_max_packet_size,
+            ));
+        }
+        for (field, value) in [
+            ("bcdDevice", self.device_version),
+            ("bcdUSB", self.usb_version),
+        ] {
+            if !is_bcd(value) {
+                return Err(ValidationError::InvalidBcd { field, value });
+            }
+        }
+        for (field, string) in [
+            ("serialnumber", &self.serialnumber),
+            ("product", &self.product),
+            ("manufacturer", &self.manufacturer),
+        ] {
+            let len = string.encode_utf16().count();
+            if len > MAX_STRING_LEN {
+                return Err(ValidationError::StringTooLong { field, len });
+            }
+        }
+
+        if self.configs.is_empty() {
+            return Err(ValidationError::NoConfigs);
+        }
+        for (config, c) in self.configs.iter().enumerate() {
+            if c.attributes & 0x80 == 0 || c.attributes & 0x1f != 0 {
+                return Err(ValidationError::InvalidAttributes {
+                    config,
+                    attributes: c.attributes,
+                });
+            }
+            let limit = max_power_limit(self.max_speed);
+            if c.max_power > limit {
+                return Err(ValidationError::MaxPowerTooHigh {
+                    config,
+                    max_power: c.max_power,
+                    limit,
+                });
+            }
+            if c.hid_functions.is_empty() {
+                return Err(ValidationError::EmptyConfig { config });
+            }
+            if let Some(function) = c
+                .hid_functions
+                .iter()
+                .find(|f| **f as usize >= self.hid_functions.len())
+            {
+                return Err(ValidationError::MissingHidFunction {
+                    config,
+                    function: *function,
+                });
+            }
+        }
+
+        for (function, f) in self.hid_functions.iter().enumerate() {
+            if f.report_length == 0 {
+                return Err(ValidationError::ZeroReportLength { function });
+            }
+            if let Err(errors) = hid::validate(&f.report_desc, f.report_length as usize) {
+                return Err(ValidationError::InvalidReportDescriptor {
+                    function,
+                    error: errors[0].clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb_gadget::hori_pad::hori_pad;
+
+    #[test]
+    fn presets_are_valid() {
+        use crate::usb_gadget::*;
+
+        let presets = [
+            ns_procon::ns_procons(),
+            ns_joycon::ns_joycons_l(),
+            ns_joycon::ns_joycons_r(),
+            nso::nes_controllers(),
+            nso::snes_controllers(),
+            nso::n64_controllers(),
+            nso::genesis_controllers(),
+            hori_pad(),
+            gc_adapter::gc_adapter(),
+            keyboard::keyboard(),
+        ];
+        for gadget in presets.iter() {
+            assert_eq!(gadget.validate(), Ok(()), "{}", gadget.product);
+        }
+    }
+
+    #[test]
+    fn max_packet_size() {
+        let mut gadget = hori_pad();
+        gadget.device_max_packet_size = 48;
+        assert_eq!(
+            gadget.validate(),
+            Err(ValidationError::InvalidMaxPacketSize(48))
+        );
+    }
+
+    #[test]
+    fn bcd_versions() {
+        let mut gadget = hori_pad();
+        gadget.usb_version = 0x20a;
+        assert_eq!(
+            gadget.validate(),
+            Err(ValidationError::InvalidBcd {
+                field: "bcdUSB",
+                value: 0x20a
+            })
+        );
+    }
+
+    #[test]
+    fn long_strings() {
+        let mut gadget = hori_pad();
+        gadget.product = "é".repeat(MAX_STRING_LEN + 1);
+        assert_eq!(
+            gadget.validate(),
+            Err(ValidationError::StringTooLong {
+                field: "product",
+                len: MAX_STRING_LEN + 1
+            })
+        );
+    }
+
+    #[test]
+    fn no_configs() {
+        let mut gadget = hori_pad();
+        gadget.configs.clear();
+        assert_eq!(gadget.validate(), Err(ValidationError::NoConfigs));
+    }
+
+    #[test]
+    fn attributes() {
+        // Bit 7 clear, then a reserved bit set
+        for attributes in [0x40, 0x81].iter() {
+            let mut gadget = hori_pad();
+            gadget.configs[0].attributes = *attributes;
+            assert_eq!(
+                gadget.validate(),
+                Err(ValidationError::InvalidAttributes {
+                    config: 0,
+                    attributes: *attributes
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn max_power_per_speed() {
+        let mut gadget = hori_pad();
+        gadget.configs[0].max_power = 900;
+        assert_eq!(
+            gadget.validate(),
+            Err(ValidationError::MaxPowerTooHigh {
+                config: 0,
+                max_power: 900,
+                limit: 500
+            })
+        );
+
+        gadget.max_speed = Speed::SuperSpeed;
+        assert_eq!(gadget.validate(), Ok(()));
+        gadget.configs[0].max_power = 901;
+        assert_eq!(
+            gadget.validate(),
+            Err(ValidationError::MaxPowerTooHigh {
+                config: 0,
+                max_power: 901,
+                limit: 900
+            })
+        );
+    }
+
+    #[test]
+    fn config_functions() {
+        let mut gadget = hori_pad();
+        gadget.configs[0].hid_functions = vec![0, 1];
+        assert_eq!(
+            gadget.validate(),
+            Err(ValidationError::MissingHidFunction {
+                config: 0,
+                function: 1
+            })
+        );
+        gadget.configs[0].hid_functions.clear();
+        assert_eq!(
+            gadget.validate(),
+            Err(ValidationError::EmptyConfig { config: 0 })
+        );
+    }
+
+    #[test]
+    fn hid_functions() {
+        let mut gadget = hori_pad();
+        gadget.hid_functions[0].report_length = 0;
+        assert_eq!(
+            gadget.validate(),
+            Err(ValidationError::ZeroReportLength { function: 0 })
+        );
+
+        gadget.hid_functions[0].report_length = 4;
+        assert!(matches!(
+            gadget.validate(),
+            Err(ValidationError::InvalidReportDescriptor {
+                function: 0,
+                error: DescriptorError::ReportTooLong { .. }
+            })
+        ));
+    }
+}